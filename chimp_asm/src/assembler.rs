@@ -0,0 +1,1069 @@
+use crate::calc;
+use crate::error::{AsmError, ErrorKind};
+use crate::lexer::{tokenize, Token};
+use crate::{parse_number, Program, START_ADDR};
+use chimp_core::Instruction;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+/// Words that cannot be used as names.
+const KEYWORDS: [&str; 57] = [
+    ":",
+    ":=",
+    "+=",
+    "-=",
+    "=-",
+    "|=",
+    "&=",
+    "^=",
+    ">>=",
+    "<<=",
+    "==",
+    "!=",
+    "<",
+    ">",
+    "<=",
+    ">=",
+    "-",
+    ";",
+    "{",
+    "}",
+    "clear",
+    "return",
+    "exit",
+    "lores",
+    "hires",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "audio",
+    "plane",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "jump",
+    "jump0",
+    "delay",
+    "buzzer",
+    "pitch",
+    "i",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "again",
+    "while",
+    "key",
+    "-key",
+    "random",
+    "hex",
+    "bighex",
+    "long",
+];
+
+/// Depth of nested macro expansions after which a macro is considered to
+/// expand into itself.
+const MAX_EXPANSION_DEPTH: usize = 1000;
+
+#[derive(Clone)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// An address operand, either known already or a label defined further down.
+enum Address {
+    Known(u16),
+    Forward(Token),
+}
+
+/// A register, or a byte, as the right hand side of an operation.
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+/// An instruction to fill in once every label is known.
+struct Fixup {
+    offset: usize,
+    instruction: fn(u16) -> Instruction,
+    /// The largest address the instruction takes.
+    max: u16,
+    label: Token,
+}
+
+/// An open control structure, with the token that opened it.
+enum Control {
+    /// The jump over the `begin` block, to patch at `else` or `end`.
+    If { token: Token, jump: usize },
+    /// The jump over the `else` block, to patch at `end`.
+    Else { token: Token, jump: usize },
+    /// The start of the loop, and the jumps out of it to patch at `again`.
+    Loop {
+        token: Token,
+        start: u16,
+        breaks: Vec<usize>,
+    },
+}
+
+pub(crate) struct Assembler {
+    /// The tokens left to assemble, in reverse order.
+    tokens: Vec<Token>,
+    /// The last token of the source, where errors about missing tokens go.
+    end: Token,
+    rom: Vec<u8>,
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, f64>,
+    aliases: BTreeMap<String, u8>,
+    macros: BTreeMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    control: Vec<Control>,
+    /// Whether the program starts with a jump to `main`.
+    main_jump: bool,
+    /// Whether an address was taken, which dropping the jump to `main` would move.
+    here_taken: bool,
+    /// For every macro being expanded, the number of tokens left once its
+    /// body is read, innermost last.
+    expansions: Vec<usize>,
+}
+
+// --- Public Methods ---
+impl Assembler {
+    pub(crate) fn new(source: &str) -> Self {
+        let mut tokens = tokenize(source);
+        let end = tokens.last().cloned().unwrap_or(Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+        });
+        tokens.reverse();
+        Self {
+            tokens,
+            end,
+            rom: Vec::new(),
+            labels: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            fixups: Vec::new(),
+            control: Vec::new(),
+            main_jump: true,
+            here_taken: false,
+            expansions: Vec::new(),
+        }
+    }
+
+    pub(crate) fn assemble(mut self) -> Result<Program> {
+        // jump main, dropped again if main is the first thing in the program
+        self.emit(Instruction::Jp(0));
+        while let Some(token) = self.tokens.pop() {
+            self.statement(token)?;
+        }
+
+        if let Some(control) = self.control.pop() {
+            let (token, message) = match control {
+                Control::If { token, .. } | Control::Else { token, .. } => {
+                    (token, "'begin' without 'end'")
+                }
+                Control::Loop { token, .. } => (token, "'loop' without 'again'"),
+            };
+            return Err(error(&token, ErrorKind::Unbalanced(message)));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&addr) = self.labels.get(&fixup.label.text) else {
+                let name = fixup.label.text.clone();
+                return Err(error(&fixup.label, ErrorKind::UndefinedName(name)));
+            };
+            let addr = to_int(f64::from(addr), &fixup.label, 0, fixup.max)?;
+            self.set_instruction(fixup.offset, (fixup.instruction)(addr));
+        }
+
+        let Some(&main) = self.labels.get("main") else {
+            return Err(error(&self.end, ErrorKind::MissingMain));
+        };
+        if self.main_jump {
+            let main = check_nnn(main, &self.end)?;
+            self.set_instruction(0, Instruction::Jp(main));
+        }
+
+        if self.rom.len() > 0x10000 - usize::from(START_ADDR) {
+            return Err(error(&self.end, ErrorKind::ProgramTooLarge));
+        }
+
+        Ok(Program {
+            rom: self.rom,
+            labels: self.labels,
+            constants: self.constants,
+        })
+    }
+}
+
+// --- Private Methods ---
+impl Assembler {
+    #[allow(clippy::too_many_lines)]
+    fn statement(&mut self, token: Token) -> Result<()> {
+        match token.text.as_str() {
+            ":" => self.label()?,
+            ":const" => {
+                let name = self.new_name()?;
+                let value = self.value()?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                if !self.aliases.contains_key(&name.text) {
+                    self.check_name(&name)?;
+                }
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.new_name()?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let byte = if self.peek_is("{") {
+                    let value = self.calc()?;
+                    to_byte(value, &token)?
+                } else {
+                    self.byte()?
+                };
+                self.rom.push(byte);
+            }
+            ":call" => {
+                let addr = self.address(0xFFF)?;
+                self.emit_address(Instruction::Call, 0xFFF, addr);
+            }
+            "clear" => self.emit(Instruction::Cls),
+            "return" | ";" => self.emit(Instruction::Ret),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "exit" => self.emit(Instruction::Exit),
+            "lores" => self.emit(Instruction::Low),
+            "hires" => self.emit(Instruction::High),
+            "audio" => self.emit(Instruction::Audio),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollDown(n));
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollUp(n));
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::Plane(n));
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdB(x));
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.emit(if save {
+                        Instruction::SaveRange { x, y }
+                    } else {
+                        Instruction::LoadRange { x, y }
+                    });
+                } else {
+                    self.emit(if save {
+                        Instruction::LdIVx(x)
+                    } else {
+                        Instruction::LdVxI(x)
+                    });
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdRVx(x));
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdVxR(x));
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Instruction::Drw { x, y, n });
+            }
+            "jump" => {
+                let addr = self.address(0xFFF)?;
+                self.emit_address(Instruction::Jp, 0xFFF, addr);
+            }
+            "jump0" => {
+                let addr = self.address(0xFFF)?;
+                self.emit_address(Instruction::JpV0, 0xFFF, addr);
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::Pitch(x),
+                });
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(token)?,
+            "else" => self.else_statement(&token)?,
+            "end" => match self.control.pop() {
+                Some(Control::If { jump, token } | Control::Else { jump, token }) => {
+                    self.patch_jump(jump, &token)?;
+                }
+                _ => {
+                    return Err(error(
+                        &token,
+                        ErrorKind::Unbalanced("'end' without 'begin'"),
+                    ))
+                }
+            },
+            "loop" => {
+                let start = self.here(&token)?;
+                self.control.push(Control::Loop {
+                    token,
+                    start,
+                    breaks: Vec::new(),
+                });
+            }
+            "while" => self.while_statement(&token)?,
+            "again" => match self.control.pop() {
+                Some(Control::Loop {
+                    token,
+                    start,
+                    breaks,
+                }) => {
+                    let start = check_nnn(start, &token)?;
+                    self.emit(Instruction::Jp(start));
+                    for jump in breaks {
+                        self.patch_jump(jump, &token)?;
+                    }
+                }
+                _ => {
+                    return Err(error(
+                        &token,
+                        ErrorKind::Unbalanced("'again' without 'loop'"),
+                    ))
+                }
+            },
+            _ => {
+                if let Some(x) = self.as_register(&token.text) {
+                    self.register_statement(x)?;
+                } else if let Some(definition) = self.macros.get(&token.text).cloned() {
+                    self.expand(&token, &definition)?;
+                } else if let Some(value) = self.constant(&token.text) {
+                    let byte = to_byte(value, &token)?;
+                    self.rom.push(byte);
+                } else if is_name(&token.text) {
+                    let addr = self.address_of(token)?;
+                    self.emit_address(Instruction::Call, 0xFFF, addr);
+                } else {
+                    return Err(error(
+                        &token,
+                        ErrorKind::UnexpectedToken(token.text.clone()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `: name`
+    fn label(&mut self) -> Result<()> {
+        let name = self.new_name()?;
+        if name.text == "main"
+            && self.main_jump
+            && self.rom.len() == 2
+            && self.labels.is_empty()
+            && !self.here_taken
+        {
+            self.rom.clear();
+            self.main_jump = false;
+        }
+        let here = self.here(&name)?;
+        self.labels.insert(name.text, here);
+        Ok(())
+    }
+
+    /// `:macro name args... { body }`
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.new_name()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+        let body = self.block()?;
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    /// Replaces a macro invocation by the body of the macro.
+    fn expand(&mut self, token: &Token, definition: &Macro) -> Result<()> {
+        let args = definition
+            .args
+            .iter()
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>>>()?;
+
+        // an expansion lasts until a token after its body is read, so one
+        // ending in a macro call is still open while that call expands
+        let left = self.tokens.len();
+        while self.expansions.last().is_some_and(|end| left < *end) {
+            self.expansions.pop();
+        }
+        if self.expansions.len() == MAX_EXPANSION_DEPTH {
+            return Err(error(token, ErrorKind::MacroRecursion(token.text.clone())));
+        }
+        self.expansions.push(left);
+
+        for body_token in definition.body.iter().rev() {
+            let arg = definition
+                .args
+                .iter()
+                .position(|arg| *arg == body_token.text);
+            self.tokens.push(match arg {
+                Some(index) => args[index].clone(),
+                None => body_token.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// `{ expression }`, as found after `:calc` and `:byte`.
+    fn calc(&mut self) -> Result<f64> {
+        let open = self.expect("{")?;
+        let tokens = self.block()?;
+        let here = f64::from(self.here(&open)?);
+        calc::evaluate(&tokens, &open, &|name| {
+            if name == "HERE" {
+                Some(here)
+            } else {
+                self.constant(name)
+                    .or_else(|| self.labels.get(name).map(|addr| f64::from(*addr)))
+            }
+        })
+    }
+
+    /// The tokens up to the `}` closing an already opened `{`.
+    fn block(&mut self) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(tokens);
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// `vx := ...`, `vx += ...` and the other register operations.
+    fn register_statement(&mut self, x: u8) -> Result<()> {
+        let op = self.next()?;
+        let instruction = match op.text.as_str() {
+            ":=" => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "random" => Instruction::Rnd {
+                        x,
+                        nn: self.byte()?,
+                    },
+                    "key" => Instruction::LdVxK(x),
+                    "delay" => Instruction::LdVxDt(x),
+                    _ => match self.operand(&source)? {
+                        Operand::Register(y) => Instruction::LdReg { x, y },
+                        Operand::Byte(nn) => Instruction::LdByte { x, nn },
+                    },
+                }
+            }
+            "+=" => {
+                let source = self.next()?;
+                match self.operand(&source)? {
+                    Operand::Register(y) => Instruction::AddReg { x, y },
+                    Operand::Byte(nn) => Instruction::AddByte { x, nn },
+                }
+            }
+            "-=" => {
+                let source = self.next()?;
+                match self.operand(&source)? {
+                    Operand::Register(y) => Instruction::Sub { x, y },
+                    Operand::Byte(nn) => Instruction::AddByte {
+                        x,
+                        nn: nn.wrapping_neg(),
+                    },
+                }
+            }
+            "|=" => Instruction::Or {
+                x,
+                y: self.register()?,
+            },
+            "&=" => Instruction::And {
+                x,
+                y: self.register()?,
+            },
+            "^=" => Instruction::Xor {
+                x,
+                y: self.register()?,
+            },
+            ">>=" => Instruction::Shr {
+                x,
+                y: self.register()?,
+            },
+            "=-" => Instruction::Subn {
+                x,
+                y: self.register()?,
+            },
+            "<<=" => Instruction::Shl {
+                x,
+                y: self.register()?,
+            },
+            _ => return Err(error(&op, ErrorKind::UnexpectedToken(op.text.clone()))),
+        };
+        self.emit(instruction);
+        Ok(())
+    }
+
+    /// `i := ...` and `i += vx`.
+    fn i_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" if self.peek_is("hex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Instruction::LdF(x));
+            }
+            ":=" if self.peek_is("bighex") => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(Instruction::LdHf(x));
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                let addr = self.address(0xFFFF)?;
+                self.emit_address(Instruction::LdILong, 0xFFFF, addr);
+            }
+            ":=" => {
+                let addr = self.address(0xFFF)?;
+                self.emit_address(Instruction::LdI, 0xFFF, addr);
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIVx(x));
+            }
+            _ => return Err(error(&op, ErrorKind::UnexpectedToken(op.text.clone()))),
+        }
+        Ok(())
+    }
+
+    /// `if condition then statement` and `if condition begin`.
+    fn if_statement(&mut self, token: Token) -> Result<()> {
+        let skip = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => self.emit(skip),
+            "begin" => {
+                self.emit(invert_skip(skip));
+                let jump = self.placeholder_jump();
+                self.control.push(Control::If { token, jump });
+            }
+            _ => {
+                return Err(error(
+                    &keyword,
+                    ErrorKind::UnexpectedToken(keyword.text.clone()),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn else_statement(&mut self, token: &Token) -> Result<()> {
+        let Some(Control::If { token: begin, jump }) = self.control.pop() else {
+            return Err(error(
+                token,
+                ErrorKind::Unbalanced("'else' without 'begin'"),
+            ));
+        };
+        let else_jump = self.placeholder_jump();
+        self.patch_jump(jump, token)?;
+        self.control.push(Control::Else {
+            token: begin,
+            jump: else_jump,
+        });
+        Ok(())
+    }
+
+    /// `while condition`, leaving the innermost loop when the condition is false.
+    fn while_statement(&mut self, token: &Token) -> Result<()> {
+        if !self
+            .control
+            .iter()
+            .any(|control| matches!(control, Control::Loop { .. }))
+        {
+            return Err(error(
+                token,
+                ErrorKind::Unbalanced("'while' outside of a loop"),
+            ));
+        }
+        let skip = self.condition()?;
+        self.emit(invert_skip(skip));
+        let jump = self.placeholder_jump();
+        if let Some(Control::Loop { breaks, .. }) = self
+            .control
+            .iter_mut()
+            .rev()
+            .find(|control| matches!(control, Control::Loop { .. }))
+        {
+            breaks.push(jump);
+        }
+        Ok(())
+    }
+
+    /// Emits what a condition needs to be tested, and returns the instruction
+    /// that skips the next one when the condition is false.
+    fn condition(&mut self) -> Result<Instruction> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let skip = match op.text.as_str() {
+            "key" => Instruction::Sknp(x),
+            "-key" => Instruction::Skp(x),
+            "==" | "!=" => {
+                let source = self.next()?;
+                match (self.operand(&source)?, op.text == "==") {
+                    (Operand::Register(y), true) => Instruction::SneReg { x, y },
+                    (Operand::Register(y), false) => Instruction::SeReg { x, y },
+                    (Operand::Byte(nn), true) => Instruction::SneByte { x, nn },
+                    (Operand::Byte(nn), false) => Instruction::SeByte { x, nn },
+                }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // compare through VF: `vf := rhs` then `vf -= vx` or `vf =- vx`,
+                // leaving the "no borrow" flag of the subtraction in VF
+                let source = self.next()?;
+                self.emit(match self.operand(&source)? {
+                    Operand::Register(y) => Instruction::LdReg { x: 0xF, y },
+                    Operand::Byte(nn) => Instruction::LdByte { x: 0xF, nn },
+                });
+                let (subtract, nn) = match op.text.as_str() {
+                    ">" => (Instruction::Sub { x: 0xF, y: x }, 1),
+                    "<=" => (Instruction::Sub { x: 0xF, y: x }, 0),
+                    "<" => (Instruction::Subn { x: 0xF, y: x }, 1),
+                    _ => (Instruction::Subn { x: 0xF, y: x }, 0),
+                };
+                self.emit(subtract);
+                Instruction::SeByte { x: 0xF, nn }
+            }
+            _ => return Err(error(&op, ErrorKind::UnexpectedToken(op.text.clone()))),
+        };
+        Ok(skip)
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.rom.extend_from_slice(&instruction.to_bytes());
+    }
+
+    /// Emits `instruction` taking an address up to `max`, leaving it to be
+    /// filled in later if the address is a label defined further down.
+    fn emit_address(&mut self, instruction: fn(u16) -> Instruction, max: u16, addr: Address) {
+        match addr {
+            Address::Known(addr) => self.emit(instruction(addr)),
+            Address::Forward(label) => {
+                self.fixups.push(Fixup {
+                    offset: self.rom.len(),
+                    instruction,
+                    max,
+                    label,
+                });
+                self.emit(instruction(0));
+            }
+        }
+    }
+
+    /// Emits a jump to be patched later, returning where it is.
+    fn placeholder_jump(&mut self) -> usize {
+        let offset = self.rom.len();
+        self.emit(Instruction::Jp(0));
+        offset
+    }
+
+    /// Makes the jump at `offset` target the current address.
+    fn patch_jump(&mut self, offset: usize, token: &Token) -> Result<()> {
+        let here = self.here(token)?;
+        self.set_instruction(offset, Instruction::Jp(check_nnn(here, token)?));
+        Ok(())
+    }
+
+    /// Overwrites the instruction at `offset`, which has the same size.
+    fn set_instruction(&mut self, offset: usize, instruction: Instruction) {
+        let bytes = instruction.to_bytes();
+        self.rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    /// The address the next byte is assembled at.
+    fn here(&mut self, token: &Token) -> Result<u16> {
+        self.here_taken = true;
+        u16::try_from(usize::from(START_ADDR) + self.rom.len())
+            .map_err(|_| error(token, ErrorKind::ProgramTooLarge))
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        self.tokens
+            .pop()
+            .ok_or_else(|| error(&self.end, ErrorKind::UnexpectedEnd))
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.last().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token> {
+        let token = self.next()?;
+        if token.text == text {
+            Ok(token)
+        } else {
+            Err(error(
+                &token,
+                ErrorKind::UnexpectedToken(token.text.clone()),
+            ))
+        }
+    }
+
+    /// A name that is not defined yet.
+    fn new_name(&mut self) -> Result<Token> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        Ok(name)
+    }
+
+    fn check_name(&self, name: &Token) -> Result<()> {
+        if !is_name(&name.text) || self.as_register(&name.text).is_some() {
+            return Err(error(name, ErrorKind::InvalidName(name.text.clone())));
+        }
+        if self.labels.contains_key(&name.text)
+            || self.constants.contains_key(&name.text)
+            || self.aliases.contains_key(&name.text)
+            || self.macros.contains_key(&name.text)
+        {
+            return Err(error(name, ErrorKind::Redefined(name.text.clone())));
+        }
+        Ok(())
+    }
+
+    fn as_register(&self, text: &str) -> Option<u8> {
+        if let Some(x) = self.aliases.get(text) {
+            return Some(*x);
+        }
+        let digit = text.strip_prefix('v')?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.as_register(&token.text)
+            .ok_or_else(|| error(&token, ErrorKind::ExpectedRegister(token.text.clone())))
+    }
+
+    /// The value of a number or a constant.
+    fn constant(&self, text: &str) -> Option<f64> {
+        parse_number(text).or_else(|| self.constants.get(text).copied())
+    }
+
+    /// A number, a constant or an already defined label.
+    fn value(&mut self) -> Result<f64> {
+        let token = self.next()?;
+        self.value_of(&token)
+    }
+
+    fn value_of(&self, token: &Token) -> Result<f64> {
+        if let Some(value) = self.constant(&token.text) {
+            return Ok(value);
+        }
+        if let Some(addr) = self.labels.get(&token.text) {
+            return Ok(f64::from(*addr));
+        }
+        let kind = if is_name(&token.text) {
+            ErrorKind::UndefinedName(token.text.clone())
+        } else {
+            ErrorKind::ExpectedValue(token.text.clone())
+        };
+        Err(error(token, kind))
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        to_byte(self.value_of(&token)?, &token)
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        let nibble = to_int(self.value_of(&token)?, &token, 0, 0xF)?;
+        Ok(nibble.to_be_bytes()[1])
+    }
+
+    fn operand(&self, token: &Token) -> Result<Operand> {
+        match self.as_register(&token.text) {
+            Some(x) => Ok(Operand::Register(x)),
+            None => Ok(Operand::Byte(to_byte(self.value_of(token)?, token)?)),
+        }
+    }
+
+    /// An address up to `max`, which may be a label defined further down.
+    fn address(&mut self, max: u16) -> Result<Address> {
+        let token = self.next()?;
+        match self.address_of(token.clone())? {
+            Address::Known(addr) => to_int(f64::from(addr), &token, 0, max).map(Address::Known),
+            forward @ Address::Forward(_) => Ok(forward),
+        }
+    }
+
+    fn address_of(&self, token: Token) -> Result<Address> {
+        if self.constant(&token.text).is_some() || self.labels.contains_key(&token.text) {
+            let value = self.value_of(&token)?;
+            return to_int(value, &token, 0, 0xFFFF).map(Address::Known);
+        }
+        if is_name(&token.text) {
+            Ok(Address::Forward(token))
+        } else {
+            Err(error(&token, ErrorKind::ExpectedValue(token.text.clone())))
+        }
+    }
+}
+
+fn error(token: &Token, kind: ErrorKind) -> AsmError {
+    AsmError::new(token.line, token.column, kind)
+}
+
+/// Whether `text` may name a label, a constant, an alias or a macro.
+fn is_name(text: &str) -> bool {
+    !text.starts_with(':')
+        && !KEYWORDS.contains(&text)
+        && parse_number(text).is_none()
+        && !text.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+}
+
+/// Truncates `value` to an integer in `min..=max` and masks it with `max`, so
+/// negative bytes come out in two's complement.
+#[allow(clippy::cast_possible_truncation)]
+fn to_int(value: f64, token: &Token, min: i64, max: u16) -> Result<u16> {
+    let int = value.trunc() as i64;
+    if int < min || int > i64::from(max) || !value.is_finite() {
+        return Err(error(token, ErrorKind::OutOfRange { value: int, max }));
+    }
+    Ok(u16::try_from(int & i64::from(max)).unwrap_or_default())
+}
+
+/// Like `to_int`, for a byte that may also be written as a negative number.
+fn to_byte(value: f64, token: &Token) -> Result<u8> {
+    let byte = to_int(value, token, -0x80, 0xFF)?;
+    Ok(byte.to_be_bytes()[1])
+}
+
+fn check_nnn(addr: u16, token: &Token) -> Result<u16> {
+    to_int(f64::from(addr), token, 0, 0xFFF)
+}
+
+/// Turns an instruction skipping the next one when a condition is false into
+/// one skipping it when the condition is true.
+fn invert_skip(skip: Instruction) -> Instruction {
+    match skip {
+        Instruction::SeByte { x, nn } => Instruction::SneByte { x, nn },
+        Instruction::SneByte { x, nn } => Instruction::SeByte { x, nn },
+        Instruction::SeReg { x, y } => Instruction::SneReg { x, y },
+        Instruction::SneReg { x, y } => Instruction::SeReg { x, y },
+        Instruction::Skp(x) => Instruction::Sknp(x),
+        Instruction::Sknp(x) => Instruction::Skp(x),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assemble, AsmError, ErrorKind};
+    use chimp_core::disasm::{disassemble, Syntax};
+
+    fn error_at(source: &str) -> (usize, usize, ErrorKind) {
+        let AsmError { line, column, kind } = assemble(source).unwrap_err();
+        (line, column, kind)
+    }
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        let source = "
+            : main
+            clear return exit lores hires scroll-down 3 scroll-up 2
+            scroll-left scroll-right audio plane 2
+            bcd v1 save v2 load v3 save v1 - v4 load v2 - v5 saveflags v6 loadflags v7
+            sprite v1 v2 5 jump 0x300 jump0 0x310 :call 0x400
+            delay := v1 buzzer := v2 pitch := v3
+            i := 0x345 i := long 0x1234 i += v4 i := hex v5 i := bighex v6
+            v1 := 0x12 v2 := v3 v4 := random 0x0F v5 := key v6 := delay
+            v1 += 3 v1 += v2 v1 -= v2 v1 |= v2 v1 &= v2 v1 ^= v2
+            v1 >>= v2 v1 =- v2 v1 <<= v2
+            if v1 == 3 then v2 := 1
+            if v1 != v2 then v2 := 1
+            if v1 key then v2 := 1
+            if v1 -key then v2 := 1
+        ";
+        let rom = assemble(source).unwrap().rom;
+
+        let mut listing = String::from(": main\n");
+        let mut code = &rom[..];
+        while !code.is_empty() {
+            let (text, len) = disassemble(code, Syntax::Octo);
+            listing.push_str(&text);
+            listing.push('\n');
+            code = &code[len..];
+        }
+        assert_eq!(assemble(&listing).unwrap().rom, rom);
+    }
+
+    #[test]
+    fn fixes_up_forward_references() {
+        let program =
+            assemble(": main jump done i := data v0 := 1 : done loop again : data 0x12").unwrap();
+        assert_eq!(
+            program.rom,
+            [0x12, 0x06, 0xA2, 0x08, 0x60, 0x01, 0x12, 0x06, 0x12]
+        );
+        assert_eq!(program.labels["done"], 0x206);
+        assert_eq!(program.labels["data"], 0x208);
+    }
+
+    #[test]
+    fn jumps_to_main_when_it_is_not_first() {
+        let program = assemble(": sub return : main sub").unwrap();
+        assert_eq!(program.rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    }
+
+    #[test]
+    fn patches_control_structures() {
+        let program =
+            assemble(": main loop if v0 == 1 begin v1 := 2 else v1 := 3 end while v2 != 4 again")
+                .unwrap();
+        assert_eq!(
+            program.rom,
+            [
+                0x30, 0x01, // 0x200 if v0 == 1 begin
+                0x12, 0x08, // 0x202 jump else
+                0x61, 0x02, // 0x204 v1 := 2
+                0x12, 0x0A, // 0x206 jump end
+                0x61, 0x03, // 0x208 v1 := 3
+                0x42, 0x04, // 0x20A while v2 != 4, skips the jump out
+                0x12, 0x10, // 0x20C jump out of the loop
+                0x12, 0x00, // 0x20E again
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn evaluates_calc() {
+        let program = assemble(
+            ": main :const base 0x10 :calc offset { base + HERE - 0x200 } :byte { offset * 2 }",
+        )
+        .unwrap();
+        assert_eq!(program.constants["offset"], 16.0);
+        assert_eq!(program.rom, [32]);
+    }
+
+    #[test]
+    fn keeps_the_jump_to_main_once_here_was_taken() {
+        let program = assemble(":calc start { HERE } : main :byte { start - 0x200 }").unwrap();
+        assert_eq!(program.labels["main"], 0x202);
+        assert_eq!(program.rom, [0x12, 0x02, 0x02]);
+
+        let program = assemble("loop : main again").unwrap();
+        assert_eq!(program.rom, [0x12, 0x02, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn expands_macros_without_a_total_limit() {
+        let mut source = String::from(":macro nothing { } : main");
+        for _ in 0..150_000 {
+            source.push_str("\nnothing");
+        }
+        assert_eq!(assemble(&source).unwrap().rom, []);
+    }
+
+    #[test]
+    fn stops_recursive_macros() {
+        for source in [
+            ":macro forever { forever } : main forever",
+            ":macro ping { v0 += 1 pong } :macro pong { ping } : main ping",
+            ":macro deep { deep v0 += 1 } : main deep",
+        ] {
+            assert!(matches!(
+                assemble(source),
+                Err(AsmError {
+                    kind: ErrorKind::MacroRecursion(_),
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(
+            error_at(": main\nv1 := v2\n  v3 += blah"),
+            (3, 9, ErrorKind::UndefinedName("blah".to_string()))
+        );
+        assert_eq!(
+            error_at(": main jump nowhere"),
+            (1, 13, ErrorKind::UndefinedName("nowhere".to_string()))
+        );
+        assert_eq!(
+            error_at(": main\n\tv1 := 256"),
+            (
+                2,
+                8,
+                ErrorKind::OutOfRange {
+                    value: 256,
+                    max: 0xFF
+                }
+            )
+        );
+        assert_eq!(
+            error_at(": main\nif v1 == 1 begin\nv2 := 3"),
+            (2, 1, ErrorKind::Unbalanced("'begin' without 'end'"))
+        );
+        assert_eq!(error_at(": main v1 :="), (1, 11, ErrorKind::UnexpectedEnd));
+        assert_eq!(
+            error_at(": main :calc x { 1 + ( 2 }"),
+            (
+                1,
+                22,
+                ErrorKind::InvalidExpression("unclosed '('".to_string())
+            )
+        );
+        assert_eq!(error_at(": start clear"), (1, 9, ErrorKind::MissingMain));
+    }
+}
@@ -0,0 +1,203 @@
+//! Evaluation of `:calc` expressions.
+//!
+//! Like in Octo, operators have no precedence: expressions are evaluated from
+//! right to left, and parentheses group sub-expressions.
+
+use crate::error::{AsmError, ErrorKind};
+use crate::lexer::Token;
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+const UNARY: [&str; 13] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor",
+];
+
+/// Evaluates `tokens`, looking names up with `lookup`.
+pub(crate) fn evaluate(
+    tokens: &[Token],
+    end: &Token,
+    lookup: &dyn Fn(&str) -> Option<f64>,
+) -> Result<f64> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end,
+        lookup,
+    };
+    let value = parser.expression()?;
+    match parser.tokens.get(parser.pos) {
+        Some(token) => Err(invalid(token, format!("unexpected '{}'", token.text))),
+        None => Ok(value),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// Where errors about a missing token are reported.
+    end: &'a Token,
+    lookup: &'a dyn Fn(&str) -> Option<f64>,
+}
+
+impl Parser<'_> {
+    fn expression(&mut self) -> Result<f64> {
+        let left = self.term()?;
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Ok(left);
+        };
+        if token.text == ")" {
+            return Ok(left);
+        }
+        self.pos += 1;
+        let right = self.expression()?;
+        binary(&token.text, left, right)
+            .ok_or_else(|| invalid(token, format!("unknown operator '{}'", token.text)))
+    }
+
+    fn term(&mut self) -> Result<f64> {
+        let Some(token) = self.tokens.get(self.pos) else {
+            return Err(invalid(self.end, "missing value".to_string()));
+        };
+        self.pos += 1;
+
+        if token.text == "(" {
+            let value = self.expression()?;
+            return match self.tokens.get(self.pos) {
+                Some(close) if close.text == ")" => {
+                    self.pos += 1;
+                    Ok(value)
+                }
+                _ => Err(invalid(token, "unclosed '('".to_string())),
+            };
+        }
+        if UNARY.contains(&token.text.as_str()) {
+            let value = self.term()?;
+            return Ok(unary(&token.text, value));
+        }
+        crate::parse_number(&token.text)
+            .or_else(|| (self.lookup)(&token.text))
+            .ok_or_else(|| {
+                AsmError::new(
+                    token.line,
+                    token.column,
+                    ErrorKind::UndefinedName(token.text.clone()),
+                )
+            })
+    }
+}
+
+fn invalid(token: &Token, message: String) -> AsmError {
+    AsmError::new(
+        token.line,
+        token.column,
+        ErrorKind::InvalidExpression(message),
+    )
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn unary(operator: &str, value: f64) -> f64 {
+    match operator {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => f64::from(u8::from(value == 0.0)),
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sign" => value.signum(),
+        "ceil" => value.ceil(),
+        _ => value.floor(),
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn binary(operator: &str, left: f64, right: f64) -> Option<f64> {
+    let (l, r) = (left as i64, right as i64);
+    let value = match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (l & r) as f64,
+        "|" => (l | r) as f64,
+        "^" => (l ^ r) as f64,
+        "<<" => l.checked_shl(r as u32).unwrap_or_default() as f64,
+        ">>" => l.checked_shr(r as u32).unwrap_or_default() as f64,
+        "<" => f64::from(u8::from(left < right)),
+        "<=" => f64::from(u8::from(left <= right)),
+        "==" => f64::from(u8::from((left - right).abs() < f64::EPSILON)),
+        "!=" => f64::from(u8::from((left - right).abs() >= f64::EPSILON)),
+        ">=" => f64::from(u8::from(left >= right)),
+        ">" => f64::from(u8::from(left > right)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn calc(source: &str) -> Result<f64> {
+        let tokens = tokenize(source);
+        let end = tokens.last().unwrap().clone();
+        evaluate(&tokens, &end, &|name| (name == "answer").then_some(42.0))
+    }
+
+    #[test]
+    fn evaluates_right_to_left() {
+        assert_eq!(calc("2 * 3 + 1"), Ok(8.0));
+        assert_eq!(calc("( 2 * 3 ) + 1"), Ok(7.0));
+        assert_eq!(calc("10 - 4 - 3"), Ok(9.0));
+        assert_eq!(calc("- 2 + 5"), Ok(3.0));
+    }
+
+    #[test]
+    fn evaluates_operators() {
+        assert_eq!(calc("0xF0 & 0x3C"), Ok(48.0));
+        assert_eq!(calc("1 << 4 | 1"), Ok(32.0));
+        assert_eq!(calc("7 % 4"), Ok(3.0));
+        assert_eq!(calc("2 pow 10"), Ok(1024.0));
+        assert_eq!(calc("3 min 5 max 4"), Ok(3.0));
+        assert_eq!(calc("floor 2.5 + sqrt 16"), Ok(6.0));
+        assert_eq!(calc("~ 0"), Ok(-1.0));
+        assert_eq!(calc("! 3"), Ok(0.0));
+        assert_eq!(calc("! 0 == 1"), Ok(1.0));
+        assert_eq!(calc("answer / 2"), Ok(21.0));
+    }
+
+    #[test]
+    fn reports_errors_at_their_token() {
+        let error = |line, column, message: &str| {
+            Err(AsmError::new(
+                line,
+                column,
+                ErrorKind::InvalidExpression(message.to_string()),
+            ))
+        };
+        assert_eq!(calc("1 +"), error(1, 3, "missing value"));
+        assert_eq!(calc("1 ? 2"), error(1, 3, "unknown operator '?'"));
+        assert_eq!(calc("( 1 + 2"), error(1, 1, "unclosed '('"));
+        assert_eq!(calc("1 + 2 )"), error(1, 7, "unexpected ')'"));
+        assert_eq!(
+            calc("1 +\n  question"),
+            Err(AsmError::new(
+                2,
+                3,
+                ErrorKind::UndefinedName("question".to_string())
+            ))
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// An error in the assembled source, at the 1-based `line` and `column` of the
+/// token that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// The source ended in the middle of a statement.
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    ExpectedRegister(String),
+    /// Neither a number, a constant nor a label.
+    ExpectedValue(String),
+    /// The value does not fit in the operand, whose largest value is `max`.
+    OutOfRange {
+        value: i64,
+        max: u16,
+    },
+    UndefinedName(String),
+    /// The name is a number, a register or a keyword.
+    InvalidName(String),
+    Redefined(String),
+    /// A control structure is missing its opening or closing keyword.
+    Unbalanced(&'static str),
+    InvalidExpression(String),
+    /// A macro keeps expanding into itself.
+    MacroRecursion(String),
+    MissingMain,
+    /// The program does not fit in the 64 KiB of XO-CHIP memory.
+    ProgramTooLarge,
+}
+
+impl AsmError {
+    pub(crate) fn new(line: usize, column: usize, kind: ErrorKind) -> Self {
+        Self { line, column, kind }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of source"),
+            Self::UnexpectedToken(token) => write!(f, "unexpected '{token}'"),
+            Self::ExpectedRegister(token) => write!(f, "expected a register, found '{token}'"),
+            Self::ExpectedValue(token) => write!(f, "expected a value, found '{token}'"),
+            Self::OutOfRange { value, max } => {
+                write!(f, "value {value} is out of range (maximum {max:#x})")
+            }
+            Self::UndefinedName(name) => write!(f, "undefined name '{name}'"),
+            Self::InvalidName(name) => write!(f, "'{name}' cannot be used as a name"),
+            Self::Redefined(name) => write!(f, "'{name}' is already defined"),
+            Self::Unbalanced(message) => write!(f, "{message}"),
+            Self::InvalidExpression(message) => write!(f, "invalid expression: {message}"),
+            Self::MacroRecursion(name) => write!(f, "macro '{name}' expands forever"),
+            Self::MissingMain => write!(f, "the program does not define 'main'"),
+            Self::ProgramTooLarge => write!(f, "the program does not fit in memory"),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
@@ -0,0 +1,57 @@
+/// A whitespace separated word of the source, with the position it starts at.
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) text: String,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+/// Splits `source` into tokens, dropping `#` comments.
+pub(crate) fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line, text) in source.lines().enumerate() {
+        let text = text.split('#').next().unwrap_or_default();
+        let mut start = None;
+        for (column, c) in text.chars().chain(Some(' ')).enumerate() {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(column),
+                (Some(first), true) => {
+                    tokens.push(Token {
+                        text: text.chars().skip(first).take(column - first).collect(),
+                        line: line + 1,
+                        column: first + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace_with_positions() {
+        let tokens = tokenize(": main # entry point\n\tv0 := 0x1F  #:=\n\n  jump main");
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.text.as_str(), token.line, token.column))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (":", 1, 1),
+                ("main", 1, 3),
+                ("v0", 2, 2),
+                (":=", 2, 5),
+                ("0x1F", 2, 8),
+                ("jump", 4, 3),
+                ("main", 4, 8),
+            ]
+        );
+    }
+}
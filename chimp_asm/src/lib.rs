@@ -0,0 +1,75 @@
+#![warn(clippy::pedantic, clippy::all)]
+//! An assembler for Octo, the CHIP-8 assembly language.
+//!
+//! Supports labels, `:const`, `:alias`, `:macro`, `:calc`, `:byte`, `:call`,
+//! every instruction of CHIP-8, SUPER-CHIP and XO-CHIP, and the `if`, `else`,
+//! `loop`, `while` and `again` control structures. The assembled ROM is meant
+//! to be loaded at 0x200, e.g. with `Vm::load_program`.
+
+mod assembler;
+mod calc;
+mod error;
+mod lexer;
+
+pub use error::{AsmError, ErrorKind};
+
+use assembler::Assembler;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The address the assembled program is loaded at.
+pub const START_ADDR: u16 = 0x200;
+
+/// An assembled program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The bytes to load at `START_ADDR`.
+    pub rom: Vec<u8>,
+    /// The address of every label.
+    pub labels: BTreeMap<String, u16>,
+    /// The value of every `:const` and `:calc`.
+    pub constants: BTreeMap<String, f64>,
+}
+
+impl Program {
+    /// Lists the labels by address, one `0x0200 main` per line.
+    #[must_use]
+    pub fn symbol_table(&self) -> String {
+        let mut labels = self.labels.iter().collect::<Vec<_>>();
+        labels.sort_by_key(|(name, addr)| (**addr, *name));
+
+        let mut table = String::new();
+        for (name, addr) in labels {
+            let _ = writeln!(table, "{addr:#06x} {name}");
+        }
+        table
+    }
+}
+
+/// Assembles Octo `source` into a program.
+///
+/// # Errors
+///
+/// Returns the first error found in the source, with its line and column.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    Assembler::new(source).assemble()
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0b` binary number, possibly negative.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
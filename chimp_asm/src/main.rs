@@ -0,0 +1,81 @@
+#![warn(clippy::pedantic, clippy::all)]
+use chimp_asm::AsmError;
+use std::env;
+use std::path::Path;
+
+type Error = Box<dyn std::error::Error>;
+type Result<T> = std::result::Result<T, Error>;
+
+struct Args {
+    source: String,
+    output: String,
+    symbols: Option<String>,
+}
+
+fn usage_error() -> Error {
+    Error::from(
+        "Invalid arguments!\n\
+         Usage: chimp_asm [-o path/to/rom] [--symbols path/to/symbols] path/to/source.8o",
+    )
+}
+
+fn parse_args() -> Result<Args> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or_else(usage_error)?),
+            "--symbols" => symbols = Some(args.next().ok_or_else(usage_error)?),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(usage_error()),
+        }
+    }
+
+    let source = source.ok_or_else(usage_error)?;
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    Ok(Args {
+        source,
+        output,
+        symbols,
+    })
+}
+
+/// Formats `error` like a compiler does, quoting the offending line.
+fn diagnostic(file_name: &str, source: &str, error: &AsmError) -> String {
+    let line = source.lines().nth(error.line - 1).unwrap_or_default();
+    format!(
+        "{file_name}:{error}\n{line}\n{:>width$}",
+        "^",
+        width = error.column
+    )
+}
+
+fn run(args: &Args) -> Result<()> {
+    let text = std::fs::read_to_string(&args.source)?;
+    let program =
+        chimp_asm::assemble(&text).map_err(|e| Error::from(diagnostic(&args.source, &text, &e)))?;
+
+    std::fs::write(&args.output, &program.rom)?;
+    if let Some(symbols) = &args.symbols {
+        std::fs::write(symbols, program.symbol_table())?;
+    }
+    println!("{} bytes written to {}", program.rom.len(), args.output);
+    Ok(())
+}
+
+fn main() {
+    let result = parse_args().and_then(|args| run(&args));
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,50 @@
+//! Measures how many instructions per second the Vm executes, with and without
+//! the decode cache. Run with `cargo bench -p chimp_core`.
+
+use chimp_core::Vm;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+/// Ticks run per iteration, with the timers ticked every `TICKS_PER_FRAME`.
+const TICKS: u64 = 100_000;
+const TICKS_PER_FRAME: u64 = 10;
+
+const ROMS: [(&str, &[u8]); 4] = [
+    ("BRIX", include_bytes!("../../roms/BRIX")),
+    ("INVADERS", include_bytes!("../../roms/INVADERS")),
+    ("KALEID", include_bytes!("../../roms/KALEID")),
+    ("TETRIS", include_bytes!("../../roms/TETRIS")),
+];
+
+fn vm(rom: &[u8], decode_cache: bool) -> Vm {
+    let mut vm = Vm::with_seed(0);
+    vm.set_decode_cache(decode_cache);
+    vm.load_program(rom)
+        .expect("the benchmark roms fit in memory");
+    vm
+}
+
+fn run(mut vm: Vm) -> Vm {
+    for tick in 0..TICKS {
+        vm.tick();
+        if tick % TICKS_PER_FRAME == 0 {
+            vm.tick_timers();
+        }
+    }
+    vm
+}
+
+fn instructions_per_second(c: &mut Criterion) {
+    for (name, rom) in ROMS {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(TICKS));
+        for (id, decode_cache) in [("uncached", false), ("cached", true)] {
+            group.bench_function(id, |b| {
+                b.iter_batched(|| vm(rom, decode_cache), run, BatchSize::LargeInput);
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, instructions_per_second);
+criterion_main!(benches);
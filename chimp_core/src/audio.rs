@@ -0,0 +1,184 @@
+//! The buzzer, which sounds while the sound timer is non-zero.
+
+use std::f32::consts::TAU;
+
+/// The shape of the buzzer's tone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    /// The harsh beep of the original hardware.
+    #[default]
+    Square,
+    Sine,
+}
+
+/// Generates the buzzer's tone as mono PCM samples, ranging over `±volume`.
+///
+/// The tone fades in and out over a couple of milliseconds, so starting and
+/// stopping it mid-wave does not click.
+#[derive(Debug, Clone)]
+pub struct Beeper {
+    sample_rate: u32,
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    /// Position in the current period, from 0 to 1.
+    phase: f32,
+    /// Current gain of the fade, from 0 (silent) to 1.
+    envelope: f32,
+}
+
+// --- Constants ---
+impl Beeper {
+    pub const DEFAULT_FREQUENCY: f32 = 440.0;
+    pub const DEFAULT_VOLUME: f32 = 0.25;
+    /// Duration of a fade in or out, in seconds.
+    const FADE_DURATION: f32 = 0.002;
+}
+
+// --- Public Methods ---
+impl Beeper {
+    /// Creates a beeper producing `sample_rate` samples per second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rate` is zero.
+    #[must_use]
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate > 0, "sample rate must not be zero");
+        Self {
+            sample_rate,
+            waveform: Waveform::default(),
+            frequency: Self::DEFAULT_FREQUENCY,
+            volume: Self::DEFAULT_VOLUME,
+            phase: 0.0,
+            envelope: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[must_use]
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Pitch of the tone, in Hz.
+    #[must_use]
+    pub fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    /// Changes the pitch of the tone, in Hz, keeping it below the Nyquist frequency.
+    /// A frequency that is not finite is ignored.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn set_frequency(&mut self, frequency: f32) {
+        if frequency.is_finite() {
+            self.frequency = frequency.clamp(0.0, self.sample_rate as f32 / 2.0);
+        }
+    }
+
+    /// Loudness of the tone, from 0 (silent) to 1.
+    #[must_use]
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Changes the loudness of the tone, from 0 (silent) to 1. A volume that
+    /// is not finite silences the tone.
+    pub fn set_volume(&mut self, volume: f32) {
+        let volume = if volume.is_finite() { volume } else { 0.0 };
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Fills `out` with the next samples, fading the tone in while `active`
+    /// (see `Vm::is_sound_active`) and out otherwise.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn fill(&mut self, active: bool, out: &mut [f32]) {
+        let sample_rate = self.sample_rate as f32;
+        let phase_step = self.frequency / sample_rate;
+        let fade_step = 1.0 / (Self::FADE_DURATION * sample_rate);
+        let target = if active { 1.0 } else { 0.0 };
+
+        for sample in out {
+            if self.envelope < target {
+                self.envelope = (self.envelope + fade_step).min(target);
+            } else if self.envelope > target {
+                self.envelope = (self.envelope - fade_step).max(target);
+            }
+            if self.envelope == 0.0 {
+                // start every tone at the beginning of a period
+                self.phase = 0.0;
+                *sample = 0.0;
+                continue;
+            }
+
+            let wave = match self.waveform {
+                Waveform::Square if self.phase < 0.5 => 1.0,
+                Waveform::Square => -1.0,
+                Waveform::Sine => (self.phase * TAU).sin(),
+            };
+            *sample = wave * self.volume * self.envelope;
+            self.phase = (self.phase + phase_step).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+// the samples checked are exact binary fractions
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    /// A beeper fading over 2 samples, with a square wave of 4 samples.
+    fn beeper() -> Beeper {
+        let mut beeper = Beeper::new(1000);
+        beeper.set_frequency(250.0);
+        beeper.set_volume(1.0);
+        beeper
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        let mut beeper = beeper();
+        let mut out = [9.0; 6];
+        beeper.fill(true, &mut out);
+        assert_eq!(out, [0.5, 1.0, -1.0, -1.0, 1.0, 1.0]);
+
+        let mut out = [9.0; 4];
+        beeper.fill(false, &mut out);
+        assert_eq!(out, [-0.5, 0.0, 0.0, 0.0]);
+
+        // the next tone starts at the beginning of a period again
+        let mut out = [9.0; 2];
+        beeper.fill(true, &mut out);
+        assert_eq!(out, [0.5, 1.0]);
+    }
+
+    #[test]
+    fn stays_silent_while_inactive() {
+        let mut beeper = beeper();
+        let mut out = [9.0; 16];
+        beeper.fill(false, &mut out);
+        assert_eq!(out, [0.0; 16]);
+    }
+
+    #[test]
+    fn ignores_nan() {
+        let mut beeper = beeper();
+        beeper.set_frequency(f32::NAN);
+        assert_eq!(beeper.frequency(), 250.0);
+        beeper.set_volume(f32::NAN);
+        assert_eq!(beeper.volume(), 0.0);
+
+        let mut out = [9.0; 8];
+        beeper.fill(true, &mut out);
+        assert_eq!(out, [0.0; 8]);
+    }
+}
@@ -0,0 +1,17 @@
+/// What a Vm does when it is ticked, see `Vm::cpu_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuState {
+    /// Executes the next instruction.
+    Running,
+    /// FX0A waits for a key to be pressed and released, as on the COSMAC VIP,
+    /// then stores it in `V[reg]`.
+    ///
+    /// Ticking does nothing meanwhile, only `Vm::keypress` moves the Vm on, so
+    /// a frontend can stop running instructions until the state changes.
+    WaitingForKey { reg: u8 },
+    /// The program exited or an error halted it, see `Vm::is_halted`.
+    Halted,
+    /// A `Debugger` stopped before the breakpoint at PC.
+    Breakpoint,
+}
@@ -0,0 +1,473 @@
+//! Breakpoints, watchpoints and stepping on top of a `Vm`.
+
+use crate::{CpuState, Instruction, Vm, VmError};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// The direction of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A data access made by an opcode, recorded while a debugger watches memory.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryAccess {
+    pub(crate) range: Range<usize>,
+    pub(crate) access: Access,
+}
+
+/// Which accesses of a watched memory range stop the execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            Self::Read => access == Access::Read,
+            Self::Write => access == Access::Write,
+            Self::ReadWrite => true,
+        }
+    }
+}
+
+/// A register whose changes can be watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    /// One of V0 to VF.
+    V(u8),
+    I,
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step, step-over or step-out completed.
+    Step,
+    /// The instruction at this address is about to run.
+    Breakpoint(u16),
+    /// The instruction at `pc` accessed the watched byte at `addr`.
+    Watchpoint {
+        pc: u16,
+        addr: usize,
+        access: Access,
+    },
+    /// The instruction at `pc` changed a watched register.
+    RegisterChanged {
+        pc: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    /// The timers ticked, ending the frame.
+    FrameEnd,
+    /// FX0A waits for a key to store in this register, which only
+    /// `Vm::keypress` can give.
+    WaitingForKey(u8),
+    /// The program exited, or faulted with the given error.
+    Halted(Option<VmError>),
+    /// The cycle limit ran out before anything else stopped the execution.
+    CycleLimit,
+}
+
+/// Wraps a `Vm` to run it under control of breakpoints and watchpoints.
+///
+/// The debugger ticks the timers itself every `cycles_per_frame` instructions,
+/// like the frontends do, so frames can be stepped through as well.
+pub struct Debugger {
+    vm: Vm,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(Range<usize>, WatchKind)>,
+    watched_registers: BTreeSet<Register>,
+    cycles_per_frame: usize,
+    frame_cycle: usize,
+    cycle_limit: u64,
+}
+
+// --- Constants ---
+impl Debugger {
+    const DEFAULT_CYCLES_PER_FRAME: usize = 10;
+    const DEFAULT_CYCLE_LIMIT: u64 = 1_000_000;
+}
+
+// --- Public Methods ---
+impl Debugger {
+    #[must_use]
+    pub fn new(mut vm: Vm) -> Self {
+        vm.access_log = Some(Vec::new());
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watched_registers: BTreeSet::new(),
+            cycles_per_frame: Self::DEFAULT_CYCLES_PER_FRAME,
+            frame_cycle: 0,
+            cycle_limit: Self::DEFAULT_CYCLE_LIMIT,
+        }
+    }
+
+    #[must_use]
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Gives the Vm back, without the debugger's instrumentation.
+    #[must_use]
+    pub fn into_inner(mut self) -> Vm {
+        self.vm.access_log = None;
+        self.vm
+    }
+
+    /// Sets how many instructions run between two timer ticks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cycles` is zero.
+    pub fn set_cycles_per_frame(&mut self, cycles: usize) {
+        assert!(cycles > 0, "a frame must run at least one cycle");
+        self.cycles_per_frame = cycles;
+    }
+
+    /// Sets how many instructions a single command may run before giving up,
+    /// so stepping over a subroutine that never returns cannot hang.
+    pub fn set_cycle_limit(&mut self, cycles: u64) {
+        self.cycle_limit = cycles;
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stops after any instruction accessing `addr..addr + len` in the way `kind` says.
+    pub fn add_watchpoint(&mut self, addr: usize, len: usize, kind: WatchKind) {
+        self.watchpoints.push((addr..addr + len, kind));
+    }
+
+    /// Removes the watchpoints covering exactly `addr..addr + len`.
+    pub fn remove_watchpoint(&mut self, addr: usize, len: usize) {
+        self.watchpoints
+            .retain(|(range, _)| *range != (addr..addr + len));
+    }
+
+    /// Stops after any instruction changing `register`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `register` is `Register::V(x)` with `x` above 0xF.
+    pub fn watch_register(&mut self, register: Register) {
+        if let Register::V(x) = register {
+            assert!(usize::from(x) < Vm::REG_COUNT, "no such register");
+        }
+        self.watched_registers.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) {
+        self.watched_registers.remove(&register);
+    }
+
+    /// Executes a single instruction, ignoring a breakpoint on it.
+    pub fn step(&mut self) -> StopReason {
+        match self.execute() {
+            None | Some(StopReason::FrameEnd) => StopReason::Step,
+            Some(reason) => reason,
+        }
+    }
+
+    /// Like `step`, but runs a whole subroutine called by 2NNN until it returns.
+    pub fn step_over(&mut self) -> StopReason {
+        if !matches!(self.instruction_at_pc(), Some(Instruction::Call(_))) {
+            return self.step();
+        }
+        let return_addr = self.vm.pc.wrapping_add(2);
+        let sp = self.vm.sp;
+        self.run_until(|vm| vm.pc == return_addr && vm.sp == sp)
+    }
+
+    /// Runs until the current subroutine returns to its caller.
+    pub fn step_out(&mut self) -> StopReason {
+        let sp = self.vm.sp;
+        if sp == 0 {
+            return self.resume();
+        }
+        self.run_until(|vm| vm.sp < sp)
+    }
+
+    /// Runs until the end of the current frame.
+    pub fn run_until_frame(&mut self) -> StopReason {
+        let mut first = true;
+        for _ in 0..self.cycle_limit {
+            if let Some(reason) = self.check_breakpoint(first) {
+                return reason;
+            }
+            first = false;
+            if let Some(reason) = self.execute() {
+                return reason;
+            }
+        }
+        StopReason::CycleLimit
+    }
+
+    /// Runs until a breakpoint, a watchpoint or the cycle limit stops it.
+    pub fn resume(&mut self) -> StopReason {
+        self.run_until(|_| false)
+    }
+}
+
+// --- Private Methods ---
+impl Debugger {
+    /// Runs until `done` holds after an instruction, or something else stops it.
+    /// Frame ends are not a reason to stop.
+    fn run_until(&mut self, done: impl Fn(&Vm) -> bool) -> StopReason {
+        let mut first = true;
+        for _ in 0..self.cycle_limit {
+            if let Some(reason) = self.check_breakpoint(first) {
+                return reason;
+            }
+            first = false;
+            match self.execute() {
+                None | Some(StopReason::FrameEnd) => {}
+                Some(reason) => return reason,
+            }
+            if done(&self.vm) {
+                return StopReason::Step;
+            }
+        }
+        StopReason::CycleLimit
+    }
+
+    /// Reports a breakpoint on the next instruction, unless it is the first one
+    /// of a command, which lets the execution continue past the breakpoint.
+    fn check_breakpoint(&mut self, first: bool) -> Option<StopReason> {
+        let pc = self.vm.pc;
+        let stop =
+            !first && self.vm.cpu_state() == CpuState::Running && self.breakpoints.contains(&pc);
+        self.vm.breakpoint = stop.then_some(pc);
+        stop.then_some(StopReason::Breakpoint(pc))
+    }
+
+    /// Executes one instruction and ends the frame when it is due, reporting
+    /// anything that should stop the execution.
+    fn execute(&mut self) -> Option<StopReason> {
+        match self.vm.cpu_state() {
+            CpuState::Halted => return Some(StopReason::Halted(self.vm.error())),
+            CpuState::WaitingForKey { reg } => return Some(StopReason::WaitingForKey(reg)),
+            CpuState::Running | CpuState::Breakpoint => {}
+        }
+
+        let pc = self.vm.pc;
+        let v_reg = self.vm.v_reg;
+        let i_reg = self.vm.i_reg;
+        if let Some(log) = &mut self.vm.access_log {
+            log.clear();
+        }
+
+        if let Err(error) = self.vm.try_tick() {
+            return Some(StopReason::Halted(Some(error)));
+        }
+        if self.vm.is_halted() {
+            return Some(StopReason::Halted(None));
+        }
+
+        let stop = self
+            .check_watchpoints(pc)
+            .or_else(|| self.check_registers(pc, &v_reg, i_reg));
+
+        self.frame_cycle += 1;
+        if self.frame_cycle >= self.cycles_per_frame {
+            self.frame_cycle = 0;
+            self.vm.tick_timers();
+            return stop.or(Some(StopReason::FrameEnd));
+        }
+        stop
+    }
+
+    fn check_watchpoints(&self, pc: u16) -> Option<StopReason> {
+        let log = self.vm.access_log.as_ref()?;
+        log.iter().find_map(|logged| {
+            self.watchpoints.iter().find_map(|(range, kind)| {
+                let start = range.start.max(logged.range.start);
+                let overlaps = start < range.end.min(logged.range.end);
+                (overlaps && kind.matches(logged.access)).then_some(StopReason::Watchpoint {
+                    pc,
+                    addr: start,
+                    access: logged.access,
+                })
+            })
+        })
+    }
+
+    fn check_registers(&self, pc: u16, v_reg: &[u8], i_reg: u16) -> Option<StopReason> {
+        self.watched_registers.iter().find_map(|register| {
+            let (old, new) = match *register {
+                Register::V(x) => {
+                    let x = usize::from(x);
+                    (u16::from(v_reg[x]), u16::from(self.vm.v_reg[x]))
+                }
+                Register::I => (i_reg, self.vm.i_reg),
+            };
+            (old != new).then_some(StopReason::RegisterChanged {
+                pc,
+                register: *register,
+                old,
+                new,
+            })
+        })
+    }
+
+    fn instruction_at_pc(&self) -> Option<Instruction> {
+        let code = self.vm.memory.get(self.vm.pc as usize..)?;
+        Instruction::read(code).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(program: &[u8]) -> Debugger {
+        let mut vm = Vm::default();
+        vm.set_quirks(crate::Quirks {
+            display_wait: false,
+            ..vm.quirks()
+        });
+        vm.load_program(program).unwrap();
+        Debugger::new(vm)
+    }
+
+    #[test]
+    fn continues_past_a_breakpoint_on_the_pc() {
+        // v0 := 1, v1 := 2, v2 := 3, jump 0x206
+        let mut debugger = debugger(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+        debugger.add_breakpoint(0x200);
+        debugger.add_breakpoint(0x202);
+        debugger.add_breakpoint(0x206);
+
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(0x202));
+        assert_eq!(debugger.vm().cpu_state(), CpuState::Breakpoint);
+        assert_eq!(debugger.vm().v_regs()[..3], [1, 0, 0]);
+
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(0x206));
+        assert_eq!(debugger.vm().v_regs()[..3], [1, 2, 3]);
+        // the jump lands on its own breakpoint, which the next command skips again
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(0x206));
+        assert_eq!(debugger.vm().cpu_state(), CpuState::Breakpoint);
+    }
+
+    #[test]
+    fn reports_watchpoints() {
+        // i := 0x300, save v2, bcd v3, sprite v0 v1 5
+        let mut debugger = debugger(&[0xA3, 0x00, 0xF2, 0x55, 0xF3, 0x33, 0xD0, 0x15]);
+        debugger.add_watchpoint(0x301, 1, WatchKind::Write);
+        let write = |pc| StopReason::Watchpoint {
+            pc,
+            addr: 0x301,
+            access: Access::Write,
+        };
+        assert_eq!(debugger.resume(), write(0x202));
+        assert_eq!(debugger.resume(), write(0x204));
+
+        debugger.remove_watchpoint(0x301, 1);
+        debugger.add_watchpoint(0x302, 8, WatchKind::Read);
+        assert_eq!(
+            debugger.resume(),
+            StopReason::Watchpoint {
+                pc: 0x206,
+                addr: 0x302,
+                access: Access::Read,
+            }
+        );
+    }
+
+    #[test]
+    fn reports_register_changes() {
+        // v0 := 5, v0 := 5, v0 += 1, i := 0x123
+        let mut debugger = debugger(&[0x60, 0x05, 0x60, 0x05, 0x70, 0x01, 0xA1, 0x23]);
+        debugger.watch_register(Register::V(0));
+        debugger.watch_register(Register::I);
+        let changed = |pc, register, old, new| StopReason::RegisterChanged {
+            pc,
+            register,
+            old,
+            new,
+        };
+        assert_eq!(debugger.resume(), changed(0x200, Register::V(0), 0, 5));
+        assert_eq!(debugger.resume(), changed(0x204, Register::V(0), 5, 6));
+        assert_eq!(debugger.resume(), changed(0x206, Register::I, 0, 0x123));
+    }
+
+    /// Calls a subroutine at 0x206 setting v1 and v2, then loops at 0x204.
+    const CALL: [u8; 12] = [
+        0x22, 0x06, // call 0x206
+        0x60, 0x01, // v0 := 1
+        0x12, 0x04, // jump 0x204
+        0x61, 0x02, // v1 := 2
+        0x62, 0x03, // v2 := 3
+        0x00, 0xEE, // return
+    ];
+
+    #[test]
+    fn steps_over_calls() {
+        let mut debugger = debugger(&CALL);
+        assert_eq!(debugger.step_over(), StopReason::Step);
+        assert_eq!(debugger.vm().pc(), 0x202);
+        assert_eq!(debugger.vm().v_regs()[..3], [0, 2, 3]);
+        assert_eq!(debugger.step_over(), StopReason::Step);
+        assert_eq!(debugger.vm().pc(), 0x204);
+
+        let mut debugger = self::debugger(&CALL);
+        debugger.add_breakpoint(0x208);
+        assert_eq!(debugger.step_over(), StopReason::Breakpoint(0x208));
+    }
+
+    #[test]
+    fn steps_out_of_subroutines() {
+        let mut debugger = debugger(&CALL);
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.step(), StopReason::Step);
+        assert_eq!(debugger.vm().pc(), 0x208);
+        assert_eq!(debugger.step_out(), StopReason::Step);
+        assert_eq!(debugger.vm().pc(), 0x202);
+        assert_eq!(debugger.vm().sp(), 0);
+        assert_eq!(debugger.vm().v_regs()[..3], [0, 2, 3]);
+    }
+
+    #[test]
+    fn runs_until_the_frame_ends() {
+        // v0 := 5, delay := v0, jump 0x204
+        let mut debugger = debugger(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+        debugger.set_cycles_per_frame(4);
+        assert_eq!(debugger.run_until_frame(), StopReason::FrameEnd);
+        assert_eq!(debugger.vm().delay_timer(), 4);
+        assert_eq!(debugger.run_until_frame(), StopReason::FrameEnd);
+        assert_eq!(debugger.vm().delay_timer(), 3);
+    }
+
+    #[test]
+    fn gives_up_at_the_cycle_limit() {
+        let mut debugger = debugger(&[0x12, 0x00]);
+        debugger.set_cycle_limit(50);
+        assert_eq!(debugger.resume(), StopReason::CycleLimit);
+        // outside of a subroutine, stepping out runs on
+        assert_eq!(debugger.step_out(), StopReason::CycleLimit);
+        debugger.set_cycles_per_frame(100);
+        assert_eq!(debugger.run_until_frame(), StopReason::CycleLimit);
+    }
+}
@@ -0,0 +1,275 @@
+//! Tracks the parts of the display that changed since a frontend last drew it.
+
+use crate::Vm;
+
+/// A rectangle of the display, in pixels of the current resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    fn contains(&self, other: &Self) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.x + other.width <= self.x + self.width
+            && other.y + other.height <= self.y + self.height
+    }
+
+    /// Returns the smallest rectangle covering both.
+    fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+// --- Constants ---
+impl Vm {
+    /// Rectangles kept apart before they are merged into their bounding box.
+    const MAX_DIRTY_RECTS: usize = 16;
+}
+
+// --- Public Methods ---
+impl Vm {
+    /// Returns `true` if the display changed since `acknowledge_display`.
+    #[must_use]
+    pub fn is_display_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Returns the parts of the display that changed since `acknowledge_display`,
+    /// the only ones a frontend keeping its previous picture has to redraw.
+    ///
+    /// Drawing and clearing mark what they touched, scrolling, switching the
+    /// resolution, resetting and loading a state mark the whole display.
+    #[must_use]
+    pub fn dirty_rects(&self) -> &[DirtyRect] {
+        &self.dirty
+    }
+
+    /// Forgets the changes, once the frontend drew them.
+    pub fn acknowledge_display(&mut self) {
+        self.dirty.clear();
+    }
+}
+
+// --- Private Methods ---
+impl Vm {
+    /// Marks the whole display of the current resolution as changed.
+    pub(crate) fn mark_display_dirty(&mut self) {
+        self.forget_unpacked();
+        self.display_touched = true;
+        self.dirty.clear();
+        self.dirty.push(self.screen_rect());
+    }
+
+    /// Marks the pixels of a sprite drawn at `x`, `y`, wrapping around or
+    /// clipped at the edges like `draw_sprite` does.
+    pub(crate) fn mark_sprite_dirty(&mut self, x: usize, y: usize, cols: usize, rows: usize) {
+        self.forget_unpacked();
+        let columns = self.sprite_spans(x, cols, self.screen_width());
+        let lines = self.sprite_spans(y, rows, self.screen_height());
+        for &(y, height) in lines.iter().flatten() {
+            for &(x, width) in columns.iter().flatten() {
+                self.mark_dirty(DirtyRect {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+            }
+        }
+    }
+
+    /// Splits `start..start + len` into the spans it covers on a `size` long
+    /// axis, a second one when it wraps around.
+    fn sprite_spans(&self, start: usize, len: usize, size: usize) -> [Option<(usize, usize)>; 2] {
+        let end = start + len;
+        if len == 0 {
+            [None, None]
+        } else if end <= size {
+            [Some((start, len)), None]
+        } else if self.quirks.clipping {
+            [Some((start, size - start)), None]
+        } else {
+            [Some((start, size - start)), Some((0, end - size))]
+        }
+    }
+
+    /// Drops the pixels `get_plane` unpacked, they no longer match the display.
+    fn forget_unpacked(&mut self) {
+        for pixels in &mut self.unpacked {
+            pixels.take();
+        }
+    }
+
+    fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.display_touched = true;
+        if self.dirty.iter().any(|dirty| dirty.contains(&rect)) {
+            return;
+        }
+        self.dirty.retain(|dirty| !rect.contains(dirty));
+        if self.dirty.len() == Self::MAX_DIRTY_RECTS {
+            let bounds = self
+                .dirty
+                .iter()
+                .fold(rect, |bounds, dirty| bounds.union(dirty));
+            self.dirty.clear();
+            self.dirty.push(bounds);
+        } else {
+            self.dirty.push(rect);
+        }
+    }
+
+    fn screen_rect(&self) -> DirtyRect {
+        DirtyRect {
+            x: 0,
+            y: 0,
+            width: self.screen_width(),
+            height: self.screen_height(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, Quirks};
+
+    /// Executes `opcode`, placed at the start of the program.
+    fn execute(vm: &mut Vm, opcode: [u8; 2]) {
+        vm.write_memory(0x200, &opcode);
+        vm.set_pc(0x200);
+        vm.try_tick().unwrap();
+    }
+
+    /// Draws `rows` rows of the font at `x`, `y`, 16 by 16 when `rows` is 0.
+    fn draw(vm: &mut Vm, x: u8, y: u8, rows: u8) {
+        vm.set_v_reg(0, x);
+        vm.set_v_reg(1, y);
+        execute(vm, [0xD0, 0x10 | rows]);
+    }
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> DirtyRect {
+        DirtyRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn starts_dirty_until_acknowledged() {
+        let mut vm = Vm::default();
+        assert!(vm.is_display_dirty());
+        assert_eq!(vm.dirty_rects(), [rect(0, 0, 64, 32)]);
+
+        vm.acknowledge_display();
+        assert!(!vm.is_display_dirty());
+        assert_eq!(vm.dirty_rects(), []);
+
+        draw(&mut vm, 10, 5, 3);
+        assert_eq!(vm.dirty_rects(), [rect(10, 5, 8, 3)]);
+        // reading does not reset them, acknowledging does
+        assert_eq!(vm.dirty_rects(), [rect(10, 5, 8, 3)]);
+        vm.acknowledge_display();
+        draw(&mut vm, 20, 6, 1);
+        assert_eq!(vm.dirty_rects(), [rect(20, 6, 8, 1)]);
+    }
+
+    #[test]
+    fn marks_lores_sprites() {
+        let mut vm = Vm::default();
+        vm.acknowledge_display();
+        draw(&mut vm, 10, 5, 3);
+        // already covered
+        draw(&mut vm, 10, 6, 2);
+        assert_eq!(vm.dirty_rects(), [rect(10, 5, 8, 3)]);
+        // covers the first one
+        draw(&mut vm, 10, 4, 5);
+        assert_eq!(vm.dirty_rects(), [rect(10, 4, 8, 5)]);
+        draw(&mut vm, 30, 20, 1);
+        assert_eq!(vm.dirty_rects(), [rect(10, 4, 8, 5), rect(30, 20, 8, 1)]);
+    }
+
+    #[test]
+    fn marks_both_sides_of_wrapped_sprites() {
+        let mut vm = Vm::default();
+        vm.acknowledge_display();
+        draw(&mut vm, 60, 30, 4);
+        assert_eq!(
+            vm.dirty_rects(),
+            [
+                rect(60, 30, 4, 2),
+                rect(0, 30, 4, 2),
+                rect(60, 0, 4, 2),
+                rect(0, 0, 4, 2),
+            ]
+        );
+
+        vm.set_quirks(Quirks {
+            clipping: true,
+            ..vm.quirks()
+        });
+        vm.acknowledge_display();
+        draw(&mut vm, 60, 30, 4);
+        assert_eq!(vm.dirty_rects(), [rect(60, 30, 4, 2)]);
+    }
+
+    #[test]
+    fn marks_hires_sprites() {
+        let mut vm = Vm::with_platform(Platform::SuperChip);
+        vm.acknowledge_display();
+        // hires
+        execute(&mut vm, [0x00, 0xFF]);
+        assert_eq!(vm.dirty_rects(), [rect(0, 0, 128, 64)]);
+
+        vm.acknowledge_display();
+        draw(&mut vm, 100, 50, 0);
+        assert_eq!(vm.dirty_rects(), [rect(100, 50, 16, 14)]);
+        draw(&mut vm, 120, 10, 5);
+        assert_eq!(
+            vm.dirty_rects(),
+            [rect(100, 50, 16, 14), rect(120, 10, 8, 5)]
+        );
+    }
+
+    #[test]
+    fn merges_too_many_sprites() {
+        let mut vm = Vm::default();
+        vm.acknowledge_display();
+        for x in 0..16 {
+            draw(&mut vm, x * 2, x, 1);
+        }
+        assert_eq!(vm.dirty_rects().len(), 16);
+        draw(&mut vm, 40, 20, 2);
+        assert_eq!(vm.dirty_rects(), [rect(0, 0, 48, 22)]);
+    }
+
+    #[test]
+    fn marks_the_whole_display_when_scrolling_or_clearing() {
+        let mut vm = Vm::with_platform(Platform::SuperChip);
+        // scroll-down 1, scroll-right, scroll-left, clear
+        for opcode in [[0x00, 0xC1], [0x00, 0xFB], [0x00, 0xFC], [0x00, 0xE0]] {
+            vm.acknowledge_display();
+            draw(&mut vm, 10, 5, 3);
+            execute(&mut vm, opcode);
+            assert_eq!(vm.dirty_rects(), [rect(0, 0, 64, 32)]);
+        }
+
+        execute(&mut vm, [0x00, 0xFF]);
+        vm.acknowledge_display();
+        execute(&mut vm, [0x00, 0xE0]);
+        assert_eq!(vm.dirty_rects(), [rect(0, 0, 128, 64)]);
+    }
+}
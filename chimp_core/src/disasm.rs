@@ -0,0 +1,268 @@
+//! Turns opcodes back into readable mnemonics.
+//!
+//! Every opcode the `Vm` knows, on any platform, is decoded. Words that are not
+//! an instruction are shown as data.
+
+use crate::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// The flavour of assembly to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Cowgod's technical reference, e.g. `LD V3, 0x1F`.
+    Cowgod,
+    /// Octo, e.g. `v3 := 0x1F`.
+    Octo,
+}
+
+/// Disassembles the instruction at the start of `code`.
+///
+/// Returns the text and the length of the instruction in bytes, which is 4 for
+/// XO-CHIP's F000 NNNN, and 1 for a lone trailing byte.
+#[must_use]
+pub fn disassemble(code: &[u8], syntax: Syntax) -> (String, usize) {
+    disassemble_with_labels(code, syntax, &BTreeMap::new())
+}
+
+/// Produces a full listing of `rom` loaded at `origin`, one instruction per line
+/// annotated with its address and bytes, and labels on jump and call targets.
+///
+/// The ROM is swept linearly, so data mixed in with the code is shown as
+/// instructions too. Octo listings keep the annotations in comments.
+#[must_use]
+pub fn listing(rom: &[u8], origin: u16, syntax: Syntax) -> String {
+    let instructions = sweep(rom);
+    let labels = find_labels(rom, origin, &instructions);
+
+    let mut out = String::new();
+    for (offset, len) in instructions {
+        let addr = usize::from(origin) + offset;
+        if let Some(label) = u16::try_from(addr).ok().and_then(|addr| labels.get(&addr)) {
+            match syntax {
+                Syntax::Cowgod => {
+                    let _ = writeln!(out, "{label}:");
+                }
+                Syntax::Octo => {
+                    let _ = writeln!(out, ": {label}");
+                }
+            }
+        }
+
+        let code = &rom[offset..];
+        let (text, _) = disassemble_with_labels(code, syntax, &labels);
+        let mut bytes = String::new();
+        for byte in &code[..len] {
+            let _ = write!(bytes, "{byte:02X}");
+        }
+        let _ = match syntax {
+            Syntax::Cowgod => writeln!(out, "{addr:#05X}  {bytes:<8}  {text}"),
+            Syntax::Octo => writeln!(out, "\t{text:<24}# {addr:#05X}  {bytes}"),
+        };
+    }
+    out
+}
+
+/// Splits `rom` into `(offset, len)` instructions from its start.
+fn sweep(rom: &[u8]) -> Vec<(usize, usize)> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let (_, len) = disassemble(&rom[offset..], Syntax::Cowgod);
+        instructions.push((offset, len));
+        offset += len;
+    }
+    instructions
+}
+
+/// Names every jump or call target that starts an instruction of the listing.
+fn find_labels(rom: &[u8], origin: u16, instructions: &[(usize, usize)]) -> BTreeMap<u16, String> {
+    let starts = instructions
+        .iter()
+        .filter_map(|(offset, _)| u16::try_from(usize::from(origin) + offset).ok())
+        .collect::<BTreeSet<u16>>();
+
+    let mut calls = BTreeSet::new();
+    let mut jumps = BTreeSet::new();
+    for (offset, _) in instructions {
+        match Instruction::read(&rom[*offset..]) {
+            Ok(Instruction::Jp(nnn) | Instruction::JpV0(nnn)) => jumps.insert(nnn),
+            Ok(Instruction::Call(nnn)) => calls.insert(nnn),
+            _ => false,
+        };
+    }
+
+    let mut labels = BTreeMap::new();
+    for addr in jumps.into_iter().filter(|addr| starts.contains(addr)) {
+        labels.insert(addr, format!("label_{addr:03X}"));
+    }
+    for addr in calls.into_iter().filter(|addr| starts.contains(addr)) {
+        labels.insert(addr, format!("sub_{addr:03X}"));
+    }
+    labels
+}
+
+fn disassemble_with_labels(
+    code: &[u8],
+    syntax: Syntax,
+    labels: &BTreeMap<u16, String>,
+) -> (String, usize) {
+    let (opcode, instruction) = match *code {
+        [] => return (String::new(), 0),
+        [byte] => return (data_byte(byte, syntax), 1),
+        [hi, lo, ..] => (u16::from_be_bytes([hi, lo]), Instruction::read(code)),
+    };
+    let Ok(instruction) = instruction else {
+        return (data_word(opcode, syntax), 2);
+    };
+    let text = match syntax {
+        Syntax::Cowgod => Some(cowgod(instruction, labels)),
+        Syntax::Octo => octo(instruction, labels),
+    };
+    match text {
+        Some(text) => (text, instruction.size()),
+        None => (data_word(opcode, syntax), 2),
+    }
+}
+
+fn data_byte(byte: u8, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("DB {byte:#04X}"),
+        Syntax::Octo => format!("{byte:#04X}"),
+    }
+}
+
+fn data_word(opcode: u16, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("DW {opcode:#06X}"),
+        Syntax::Octo => format!("{:#04X} {:#04X}", opcode >> 8, opcode & 0xFF),
+    }
+}
+
+fn address(addr: u16, labels: &BTreeMap<u16, String>) -> String {
+    labels
+        .get(&addr)
+        .cloned()
+        .unwrap_or_else(|| format!("{addr:#05X}"))
+}
+
+fn cowgod(instruction: Instruction, labels: &BTreeMap<u16, String>) -> String {
+    #[rustfmt::skip]
+    let text = match instruction {
+        Instruction::Nop                => "SYS 0x000".to_string(),
+        Instruction::ScrollDown(n)      => format!("SCD {n}"),
+        Instruction::ScrollUp(n)        => format!("SCU {n}"),
+        Instruction::Cls                => "CLS".to_string(),
+        Instruction::Ret                => "RET".to_string(),
+        Instruction::ScrollRight        => "SCR".to_string(),
+        Instruction::ScrollLeft         => "SCL".to_string(),
+        Instruction::Exit               => "EXIT".to_string(),
+        Instruction::Low                => "LOW".to_string(),
+        Instruction::High               => "HIGH".to_string(),
+        Instruction::Jp(nnn)            => format!("JP {}", address(nnn, labels)),
+        Instruction::Call(nnn)          => format!("CALL {}", address(nnn, labels)),
+        Instruction::SeByte { x, nn }   => format!("SE V{x:X}, {nn:#04X}"),
+        Instruction::SneByte { x, nn }  => format!("SNE V{x:X}, {nn:#04X}"),
+        Instruction::SeReg { x, y }     => format!("SE V{x:X}, V{y:X}"),
+        Instruction::SaveRange { x, y } => format!("LD [I], V{x:X}-V{y:X}"),
+        Instruction::LoadRange { x, y } => format!("LD V{x:X}-V{y:X}, [I]"),
+        Instruction::LdByte { x, nn }   => format!("LD V{x:X}, {nn:#04X}"),
+        Instruction::AddByte { x, nn }  => format!("ADD V{x:X}, {nn:#04X}"),
+        Instruction::LdReg { x, y }     => format!("LD V{x:X}, V{y:X}"),
+        Instruction::Or { x, y }        => format!("OR V{x:X}, V{y:X}"),
+        Instruction::And { x, y }       => format!("AND V{x:X}, V{y:X}"),
+        Instruction::Xor { x, y }       => format!("XOR V{x:X}, V{y:X}"),
+        Instruction::AddReg { x, y }    => format!("ADD V{x:X}, V{y:X}"),
+        Instruction::Sub { x, y }       => format!("SUB V{x:X}, V{y:X}"),
+        Instruction::Shr { x, y }       => format!("SHR V{x:X}, V{y:X}"),
+        Instruction::Subn { x, y }      => format!("SUBN V{x:X}, V{y:X}"),
+        Instruction::Shl { x, y }       => format!("SHL V{x:X}, V{y:X}"),
+        Instruction::SneReg { x, y }    => format!("SNE V{x:X}, V{y:X}"),
+        Instruction::LdI(nnn)           => format!("LD I, {}", address(nnn, labels)),
+        Instruction::JpV0(nnn)          => format!("JP V0, {}", address(nnn, labels)),
+        Instruction::Rnd { x, nn }      => format!("RND V{x:X}, {nn:#04X}"),
+        Instruction::Drw { x, y, n }    => format!("DRW V{x:X}, V{y:X}, {n}"),
+        Instruction::Skp(x)             => format!("SKP V{x:X}"),
+        Instruction::Sknp(x)            => format!("SKNP V{x:X}"),
+        Instruction::LdILong(nnnn)      => format!("LD I, long {}", address(nnnn, labels)),
+        Instruction::Plane(n)           => format!("PLANE {n}"),
+        Instruction::Audio              => "AUDIO".to_string(),
+        Instruction::LdVxDt(x)          => format!("LD V{x:X}, DT"),
+        Instruction::LdVxK(x)           => format!("LD V{x:X}, K"),
+        Instruction::LdDtVx(x)          => format!("LD DT, V{x:X}"),
+        Instruction::LdStVx(x)          => format!("LD ST, V{x:X}"),
+        Instruction::AddIVx(x)          => format!("ADD I, V{x:X}"),
+        Instruction::LdF(x)             => format!("LD F, V{x:X}"),
+        Instruction::LdHf(x)            => format!("LD HF, V{x:X}"),
+        Instruction::LdB(x)             => format!("LD B, V{x:X}"),
+        Instruction::Pitch(x)           => format!("PITCH V{x:X}"),
+        Instruction::LdIVx(x)           => format!("LD [I], V{x:X}"),
+        Instruction::LdVxI(x)           => format!("LD V{x:X}, [I]"),
+        Instruction::LdRVx(x)           => format!("LD R, V{x:X}"),
+        Instruction::LdVxR(x)           => format!("LD V{x:X}, R"),
+    };
+    text
+}
+
+/// Octo has no mnemonic for 0000, which is shown as data instead.
+fn octo(instruction: Instruction, labels: &BTreeMap<u16, String>) -> Option<String> {
+    #[rustfmt::skip]
+    let text = match instruction {
+        Instruction::Nop                => return None,
+        Instruction::ScrollDown(n)      => format!("scroll-down {n}"),
+        Instruction::ScrollUp(n)        => format!("scroll-up {n}"),
+        Instruction::Cls                => "clear".to_string(),
+        Instruction::Ret                => "return".to_string(),
+        Instruction::ScrollRight        => "scroll-right".to_string(),
+        Instruction::ScrollLeft         => "scroll-left".to_string(),
+        Instruction::Exit               => "exit".to_string(),
+        Instruction::Low                => "lores".to_string(),
+        Instruction::High               => "hires".to_string(),
+        Instruction::Jp(nnn)            => format!("jump {}", address(nnn, labels)),
+        Instruction::Call(nnn)          => match labels.get(&nnn) {
+            Some(label) => label.clone(),
+            None => format!(":call {nnn:#05X}"),
+        },
+        Instruction::SeByte { x, nn }   => format!("if v{x:x} != {nn:#04X} then"),
+        Instruction::SneByte { x, nn }  => format!("if v{x:x} == {nn:#04X} then"),
+        Instruction::SeReg { x, y }     => format!("if v{x:x} != v{y:x} then"),
+        Instruction::SaveRange { x, y } => format!("save v{x:x} - v{y:x}"),
+        Instruction::LoadRange { x, y } => format!("load v{x:x} - v{y:x}"),
+        Instruction::LdByte { x, nn }   => format!("v{x:x} := {nn:#04X}"),
+        Instruction::AddByte { x, nn }  => format!("v{x:x} += {nn:#04X}"),
+        Instruction::LdReg { x, y }     => format!("v{x:x} := v{y:x}"),
+        Instruction::Or { x, y }        => format!("v{x:x} |= v{y:x}"),
+        Instruction::And { x, y }       => format!("v{x:x} &= v{y:x}"),
+        Instruction::Xor { x, y }       => format!("v{x:x} ^= v{y:x}"),
+        Instruction::AddReg { x, y }    => format!("v{x:x} += v{y:x}"),
+        Instruction::Sub { x, y }       => format!("v{x:x} -= v{y:x}"),
+        Instruction::Shr { x, y }       => format!("v{x:x} >>= v{y:x}"),
+        Instruction::Subn { x, y }      => format!("v{x:x} =- v{y:x}"),
+        Instruction::Shl { x, y }       => format!("v{x:x} <<= v{y:x}"),
+        Instruction::SneReg { x, y }    => format!("if v{x:x} == v{y:x} then"),
+        Instruction::LdI(nnn)           => format!("i := {}", address(nnn, labels)),
+        Instruction::JpV0(nnn)          => format!("jump0 {}", address(nnn, labels)),
+        Instruction::Rnd { x, nn }      => format!("v{x:x} := random {nn:#04X}"),
+        Instruction::Drw { x, y, n }    => format!("sprite v{x:x} v{y:x} {n}"),
+        Instruction::Skp(x)             => format!("if v{x:x} -key then"),
+        Instruction::Sknp(x)            => format!("if v{x:x} key then"),
+        Instruction::LdILong(nnnn)      => format!("i := long {}", address(nnnn, labels)),
+        Instruction::Plane(n)           => format!("plane {n}"),
+        Instruction::Audio              => "audio".to_string(),
+        Instruction::LdVxDt(x)          => format!("v{x:x} := delay"),
+        Instruction::LdVxK(x)           => format!("v{x:x} := key"),
+        Instruction::LdDtVx(x)          => format!("delay := v{x:x}"),
+        Instruction::LdStVx(x)          => format!("buzzer := v{x:x}"),
+        Instruction::AddIVx(x)          => format!("i += v{x:x}"),
+        Instruction::LdF(x)             => format!("i := hex v{x:x}"),
+        Instruction::LdHf(x)            => format!("i := bighex v{x:x}"),
+        Instruction::LdB(x)             => format!("bcd v{x:x}"),
+        Instruction::Pitch(x)           => format!("pitch := v{x:x}"),
+        Instruction::LdIVx(x)           => format!("save v{x:x}"),
+        Instruction::LdVxI(x)           => format!("load v{x:x}"),
+        Instruction::LdRVx(x)           => format!("saveflags v{x:x}"),
+        Instruction::LdVxR(x)           => format!("loadflags v{x:x}"),
+    };
+    Some(text)
+}
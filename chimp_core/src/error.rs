@@ -0,0 +1,119 @@
+use crate::Platform;
+use std::fmt;
+
+/// An error raised while executing a program, after which the Vm stays halted.
+///
+/// Every variant carries the address of the faulting instruction (`pc`) and
+/// the `opcode` found there. When the fault happens while fetching the opcode
+/// itself, `opcode` is `0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VmError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16, opcode: u16 },
+    StackUnderflow { pc: u16, opcode: u16 },
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    InvalidKey { pc: u16, opcode: u16, key: u8 },
+}
+
+impl VmError {
+    /// Address of the instruction that caused the error.
+    #[must_use]
+    pub fn pc(&self) -> u16 {
+        match *self {
+            Self::UnknownOpcode { pc, .. }
+            | Self::StackOverflow { pc, .. }
+            | Self::StackUnderflow { pc, .. }
+            | Self::MemoryOutOfBounds { pc, .. }
+            | Self::InvalidKey { pc, .. } => pc,
+        }
+    }
+
+    /// Opcode of the instruction that caused the error.
+    #[must_use]
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            Self::UnknownOpcode { opcode, .. }
+            | Self::StackOverflow { opcode, .. }
+            | Self::StackUnderflow { opcode, .. }
+            | Self::MemoryOutOfBounds { opcode, .. }
+            | Self::InvalidKey { opcode, .. } => opcode,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {opcode:#06x} at pc {pc:#05x}")
+            }
+            Self::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow by {opcode:#06x} at pc {pc:#05x}")
+            }
+            Self::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow by {opcode:#06x} at pc {pc:#05x}")
+            }
+            Self::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "memory access out of bounds ({addr:#x}) by {opcode:#06x} at pc {pc:#05x}"
+            ),
+            Self::InvalidKey { pc, opcode, key } => {
+                write!(f, "invalid key ({key:#x}) by {opcode:#06x} at pc {pc:#05x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// An error loading a program into a Vm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The program is `size` bytes long but only `max` fit after the load address.
+    RomTooLarge {
+        size: usize,
+        max: usize,
+        platform: Platform,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::RomTooLarge {
+                size,
+                max,
+                platform,
+            } => write!(
+                f,
+                "ROM too large for {}: {size} bytes, at most {max} fit",
+                platform.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// The reason an opcode handler failed, before it is tagged with `pc` and `opcode`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+    InvalidKey(u8),
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> VmError {
+        match self {
+            Self::UnknownOpcode => VmError::UnknownOpcode { pc, opcode },
+            Self::StackOverflow => VmError::StackOverflow { pc, opcode },
+            Self::StackUnderflow => VmError::StackUnderflow { pc, opcode },
+            Self::MemoryOutOfBounds(addr) => VmError::MemoryOutOfBounds { pc, opcode, addr },
+            Self::InvalidKey(key) => VmError::InvalidKey { pc, opcode, key },
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use crate::VmError;
+
+/// What happened during a frame, as returned by `Vm::run_frame`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameReport {
+    /// Instructions actually executed, fewer than asked when the Vm halted or
+    /// started waiting for a key or the display.
+    pub instructions: u32,
+    /// Whether the frame drew to, cleared or scrolled the display or switched
+    /// its resolution, if not the frontend can keep the picture it has.
+    pub display_changed: bool,
+    /// Whether the buzzer sounds after the timers ticked, see `Vm::is_sound_active`.
+    pub sound_active: bool,
+    /// Whether FX0A waits for a key, see `CpuState::WaitingForKey`.
+    pub waiting_for_key: bool,
+    /// The error that halted the Vm, during this frame or before.
+    pub error: Option<VmError>,
+}
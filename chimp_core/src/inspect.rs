@@ -0,0 +1,164 @@
+//! Read access to the whole state of a `Vm`, and the setters a debugger needs.
+
+use crate::{CpuState, Platform, Quirks, Vm, VmError};
+
+/// A copy of everything a UI shows about a Vm, taken by `Vm::snapshot`.
+///
+/// The display is left out, `Vm::get_rows` gives it without copying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VmSnapshot {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub cpu_state: CpuState,
+    pub error: Option<VmError>,
+    pub pc: u16,
+    pub i_reg: u16,
+    pub v_reg: [u8; Vm::REG_COUNT],
+    /// Number of return addresses on the stack.
+    pub sp: usize,
+    /// The whole stack, only the first `sp` entries are in use.
+    pub stack: [u16; Vm::STACK_SIZE],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [bool; Vm::KEYS_COUNT],
+    pub rpl: [u8; Vm::RPL_COUNT],
+    pub hires: bool,
+    /// Bitplanes selected by the XO-CHIP FN01, bit 0 for the first one.
+    pub planes: u8,
+    pub pitch: u8,
+    pub memory: Vec<u8>,
+}
+
+// --- Public Methods ---
+impl Vm {
+    /// Copies the state of the Vm in one call, see `VmSnapshot`.
+    #[must_use]
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            platform: self.platform,
+            quirks: self.quirks,
+            cpu_state: self.cpu_state(),
+            error: self.error,
+            pc: self.pc,
+            i_reg: self.i_reg,
+            v_reg: self.v_reg,
+            sp: self.sp(),
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            keys: self.keys,
+            rpl: self.rpl,
+            hires: self.hires,
+            planes: self.planes,
+            pitch: self.pitch,
+            memory: self.memory.clone(),
+        }
+    }
+
+    /// Address of the next instruction.
+    #[must_use]
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    #[must_use]
+    pub fn i_reg(&self) -> u16 {
+        self.i_reg
+    }
+
+    /// Returns V0 to VF.
+    #[must_use]
+    pub fn v_regs(&self) -> &[u8; Self::REG_COUNT] {
+        &self.v_reg
+    }
+
+    /// Returns register V`x`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is above 0xF.
+    #[must_use]
+    pub fn v_reg(&self, x: usize) -> u8 {
+        self.v_reg[x]
+    }
+
+    /// Number of return addresses on the stack.
+    #[must_use]
+    pub fn sp(&self) -> usize {
+        usize::from(self.sp)
+    }
+
+    /// Returns the return addresses on the stack, the latest one last.
+    #[must_use]
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp()]
+    }
+
+    #[must_use]
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    #[must_use]
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Returns whether each key of the keypad is pressed, indexed by its value.
+    #[must_use]
+    pub fn keys(&self) -> &[bool; Self::KEYS_COUNT] {
+        &self.keys
+    }
+
+    /// Returns the SUPER-CHIP RPL user flags.
+    #[must_use]
+    pub fn rpl(&self) -> &[u8; Self::RPL_COUNT] {
+        &self.rpl
+    }
+
+    /// Returns the bitplanes selected by the XO-CHIP FN01, bit 0 for the first one.
+    #[must_use]
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Returns the whole memory, fonts included.
+    #[must_use]
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Moves the Program Counter, e.g. to skip an instruction. A pending FX0A
+    /// is cancelled.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+        self.key_wait = None;
+        self.breakpoint = None;
+    }
+
+    pub fn set_i_reg(&mut self, value: u16) {
+        self.i_reg = value;
+    }
+
+    /// Changes register V`x`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is above 0xF.
+    pub fn set_v_reg(&mut self, x: usize, value: u8) {
+        self.v_reg[x] = value;
+    }
+
+    /// Writes `data` to memory at `addr`, the instructions it overwrites are
+    /// decoded again when they run.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not fit between `addr` and the end of memory.
+    pub fn write_memory(&mut self, addr: usize, data: &[u8]) {
+        let range = addr..addr + data.len();
+        self.invalidate_decode_cache(&range);
+        self.memory[range].copy_from_slice(data);
+    }
+}
@@ -0,0 +1,394 @@
+use crate::Platform;
+use std::fmt;
+
+/// A decoded instruction of CHIP-8, SUPER-CHIP or XO-CHIP.
+///
+/// The names follow Cowgod's technical reference where it has one. `x` and
+/// `y` name the registers VX and VY, `nn` is an immediate byte and `n` a nibble.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// 0000, does nothing.
+    Nop,
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    Low,
+    /// 00FF
+    High,
+    /// 1NNN
+    Jp(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SeByte { x: u8, nn: u8 },
+    /// 4XNN
+    SneByte { x: u8, nn: u8 },
+    /// 5XY0
+    SeReg { x: u8, y: u8 },
+    /// 5XY2
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    LdByte { x: u8, nn: u8 },
+    /// 7XNN
+    AddByte { x: u8, nn: u8 },
+    /// 8XY0
+    LdReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    Shr { x: u8, y: u8 },
+    /// 8XY7
+    Subn { x: u8, y: u8 },
+    /// 8XYE
+    Shl { x: u8, y: u8 },
+    /// 9XY0
+    SneReg { x: u8, y: u8 },
+    /// ANNN
+    LdI(u16),
+    /// BNNN
+    JpV0(u16),
+    /// CXNN
+    Rnd { x: u8, nn: u8 },
+    /// DXYN
+    Drw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    Skp(u8),
+    /// EXA1
+    Sknp(u8),
+    /// F000 NNNN, the only instruction taking two words.
+    LdILong(u16),
+    /// FN01
+    Plane(u8),
+    /// F002
+    Audio,
+    /// FX07
+    LdVxDt(u8),
+    /// FX0A
+    LdVxK(u8),
+    /// FX15
+    LdDtVx(u8),
+    /// FX18
+    LdStVx(u8),
+    /// FX1E
+    AddIVx(u8),
+    /// FX29
+    LdF(u8),
+    /// FX30
+    LdHf(u8),
+    /// FX33
+    LdB(u8),
+    /// FX3A
+    Pitch(u8),
+    /// FX55
+    LdIVx(u8),
+    /// FX65
+    LdVxI(u8),
+    /// FX75
+    LdRVx(u8),
+    /// FX85
+    LdVxR(u8),
+}
+
+/// The reason some bytes are not an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(u16),
+    /// The bytes end before the instruction does.
+    Truncated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOpcode(opcode) => write!(f, "unknown opcode {opcode:#06x}"),
+            Self::Truncated => write!(f, "truncated instruction"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    /// Decodes a single opcode.
+    ///
+    /// F000 decodes to `LdILong(0)`, as its address is the word that follows,
+    /// see `read` to decode it whole.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::UnknownOpcode` if no platform knows the opcode.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn decode(opcode: u16) -> Result<Self, DecodeError> {
+        let digit_1 = (opcode & 0xF000) >> 12;
+        let digit_2 = (opcode & 0x0F00) >> 8;
+        let digit_3 = (opcode & 0x00F0) >> 4;
+        let digit_4 = opcode & 0x000F;
+        let x = digit_2 as u8;
+        let y = digit_3 as u8;
+        let n = digit_4 as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        #[rustfmt::skip]
+        let instruction = match (digit_1, digit_2, digit_3, digit_4) {
+            (0x0, 0x0, 0x0, 0x0) => Self::Nop,
+            (0x0, 0x0, 0xC,   _) => Self::ScrollDown(n),
+            (0x0, 0x0, 0xD,   _) => Self::ScrollUp(n),
+            (0x0, 0x0, 0xE, 0x0) => Self::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Self::Ret,
+            (0x0, 0x0, 0xF, 0xB) => Self::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Self::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Self::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Self::Low,
+            (0x0, 0x0, 0xF, 0xF) => Self::High,
+            (0x1,   _,   _,   _) => Self::Jp(nnn),
+            (0x2,   _,   _,   _) => Self::Call(nnn),
+            (0x3,   _,   _,   _) => Self::SeByte { x, nn },
+            (0x4,   _,   _,   _) => Self::SneByte { x, nn },
+            (0x5,   _,   _, 0x0) => Self::SeReg { x, y },
+            (0x5,   _,   _, 0x2) => Self::SaveRange { x, y },
+            (0x5,   _,   _, 0x3) => Self::LoadRange { x, y },
+            (0x6,   _,   _,   _) => Self::LdByte { x, nn },
+            (0x7,   _,   _,   _) => Self::AddByte { x, nn },
+            (0x8,   _,   _, 0x0) => Self::LdReg { x, y },
+            (0x8,   _,   _, 0x1) => Self::Or { x, y },
+            (0x8,   _,   _, 0x2) => Self::And { x, y },
+            (0x8,   _,   _, 0x3) => Self::Xor { x, y },
+            (0x8,   _,   _, 0x4) => Self::AddReg { x, y },
+            (0x8,   _,   _, 0x5) => Self::Sub { x, y },
+            (0x8,   _,   _, 0x6) => Self::Shr { x, y },
+            (0x8,   _,   _, 0x7) => Self::Subn { x, y },
+            (0x8,   _,   _, 0xE) => Self::Shl { x, y },
+            (0x9,   _,   _, 0x0) => Self::SneReg { x, y },
+            (0xA,   _,   _,   _) => Self::LdI(nnn),
+            (0xB,   _,   _,   _) => Self::JpV0(nnn),
+            (0xC,   _,   _,   _) => Self::Rnd { x, nn },
+            (0xD,   _,   _,   _) => Self::Drw { x, y, n },
+            (0xE,   _, 0x9, 0xE) => Self::Skp(x),
+            (0xE,   _, 0xA, 0x1) => Self::Sknp(x),
+            (0xF, 0x0, 0x0, 0x0) => Self::LdILong(0),
+            (0xF,   _, 0x0, 0x1) => Self::Plane(x),
+            (0xF, 0x0, 0x0, 0x2) => Self::Audio,
+            (0xF,   _, 0x0, 0x7) => Self::LdVxDt(x),
+            (0xF,   _, 0x0, 0xA) => Self::LdVxK(x),
+            (0xF,   _, 0x1, 0x5) => Self::LdDtVx(x),
+            (0xF,   _, 0x1, 0x8) => Self::LdStVx(x),
+            (0xF,   _, 0x1, 0xE) => Self::AddIVx(x),
+            (0xF,   _, 0x2, 0x9) => Self::LdF(x),
+            (0xF,   _, 0x3, 0x0) => Self::LdHf(x),
+            (0xF,   _, 0x3, 0x3) => Self::LdB(x),
+            (0xF,   _, 0x3, 0xA) => Self::Pitch(x),
+            (0xF,   _, 0x5, 0x5) => Self::LdIVx(x),
+            (0xF,   _, 0x6, 0x5) => Self::LdVxI(x),
+            (0xF,   _, 0x7, 0x5) => Self::LdRVx(x),
+            (0xF,   _, 0x8, 0x5) => Self::LdVxR(x),
+            (  _,   _,   _,   _) => return Err(DecodeError::UnknownOpcode(opcode)),
+        };
+        Ok(instruction)
+    }
+
+    /// Decodes the instruction at the start of `code`, including the address
+    /// of F000 NNNN.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::Truncated` if `code` ends within the instruction,
+    /// and `DecodeError::UnknownOpcode` if no platform knows its opcode.
+    pub fn read(code: &[u8]) -> Result<Self, DecodeError> {
+        let word = |at: usize| {
+            code.get(at..at + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .ok_or(DecodeError::Truncated)
+        };
+        match Self::decode(word(0)?)? {
+            Self::LdILong(_) => Ok(Self::LdILong(word(2)?)),
+            instruction => Ok(instruction),
+        }
+    }
+
+    /// Encodes the instruction into its opcode, which is only the first word
+    /// for `LdILong`. Operands are truncated to the bits the opcode has for them.
+    #[must_use]
+    pub fn encode(self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8| {
+            base | ((u16::from(x) & 0xF) << 8) | ((u16::from(y) & 0xF) << 4)
+        };
+        let xnn = |base: u16, x: u8, nn: u8| base | ((u16::from(x) & 0xF) << 8) | u16::from(nn);
+        let x = |base: u16, x: u8| xy(base, x, 0);
+
+        match self {
+            Self::Nop => 0x0000,
+            Self::ScrollDown(n) => 0x00C0 | (u16::from(n) & 0xF),
+            Self::ScrollUp(n) => 0x00D0 | (u16::from(n) & 0xF),
+            Self::Cls => 0x00E0,
+            Self::Ret => 0x00EE,
+            Self::ScrollRight => 0x00FB,
+            Self::ScrollLeft => 0x00FC,
+            Self::Exit => 0x00FD,
+            Self::Low => 0x00FE,
+            Self::High => 0x00FF,
+            Self::Jp(nnn) => 0x1000 | (nnn & 0xFFF),
+            Self::Call(nnn) => 0x2000 | (nnn & 0xFFF),
+            Self::SeByte { x, nn } => xnn(0x3000, x, nn),
+            Self::SneByte { x, nn } => xnn(0x4000, x, nn),
+            Self::SeReg { x, y } => xy(0x5000, x, y),
+            Self::SaveRange { x, y } => xy(0x5002, x, y),
+            Self::LoadRange { x, y } => xy(0x5003, x, y),
+            Self::LdByte { x, nn } => xnn(0x6000, x, nn),
+            Self::AddByte { x, nn } => xnn(0x7000, x, nn),
+            Self::LdReg { x, y } => xy(0x8000, x, y),
+            Self::Or { x, y } => xy(0x8001, x, y),
+            Self::And { x, y } => xy(0x8002, x, y),
+            Self::Xor { x, y } => xy(0x8003, x, y),
+            Self::AddReg { x, y } => xy(0x8004, x, y),
+            Self::Sub { x, y } => xy(0x8005, x, y),
+            Self::Shr { x, y } => xy(0x8006, x, y),
+            Self::Subn { x, y } => xy(0x8007, x, y),
+            Self::Shl { x, y } => xy(0x800E, x, y),
+            Self::SneReg { x, y } => xy(0x9000, x, y),
+            Self::LdI(nnn) => 0xA000 | (nnn & 0xFFF),
+            Self::JpV0(nnn) => 0xB000 | (nnn & 0xFFF),
+            Self::Rnd { x, nn } => xnn(0xC000, x, nn),
+            Self::Drw { x, y, n } => xy(0xD000, x, y) | (u16::from(n) & 0xF),
+            Self::Skp(vx) => x(0xE09E, vx),
+            Self::Sknp(vx) => x(0xE0A1, vx),
+            Self::LdILong(_) => 0xF000,
+            Self::Plane(n) => x(0xF001, n),
+            Self::Audio => 0xF002,
+            Self::LdVxDt(vx) => x(0xF007, vx),
+            Self::LdVxK(vx) => x(0xF00A, vx),
+            Self::LdDtVx(vx) => x(0xF015, vx),
+            Self::LdStVx(vx) => x(0xF018, vx),
+            Self::AddIVx(vx) => x(0xF01E, vx),
+            Self::LdF(vx) => x(0xF029, vx),
+            Self::LdHf(vx) => x(0xF030, vx),
+            Self::LdB(vx) => x(0xF033, vx),
+            Self::Pitch(vx) => x(0xF03A, vx),
+            Self::LdIVx(vx) => x(0xF055, vx),
+            Self::LdVxI(vx) => x(0xF065, vx),
+            Self::LdRVx(vx) => x(0xF075, vx),
+            Self::LdVxR(vx) => x(0xF085, vx),
+        }
+    }
+
+    /// Encodes the whole instruction, the way it is stored in memory.
+    #[must_use]
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Self::LdILong(nnnn) = self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Size of the instruction in memory, in bytes.
+    #[must_use]
+    pub fn size(self) -> usize {
+        match self {
+            Self::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// The first platform to have the instruction, the later ones keep it.
+    #[must_use]
+    pub fn platform(self) -> Platform {
+        match self {
+            Self::ScrollDown(_)
+            | Self::ScrollRight
+            | Self::ScrollLeft
+            | Self::Exit
+            | Self::Low
+            | Self::High
+            | Self::LdHf(_)
+            | Self::LdRVx(_)
+            | Self::LdVxR(_) => Platform::SuperChip,
+            Self::ScrollUp(_)
+            | Self::SaveRange { .. }
+            | Self::LoadRange { .. }
+            | Self::LdILong(_)
+            | Self::Plane(_)
+            | Self::Audio
+            | Self::Pitch(_) => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_opcode_back() {
+        let mut known = 0;
+        for opcode in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction:?}");
+                known += 1;
+            }
+        }
+        // spot check that the unknown opcodes are the ones left out
+        assert_eq!(
+            Instruction::decode(0x5001),
+            Err(DecodeError::UnknownOpcode(0x5001))
+        );
+        assert_eq!(
+            Instruction::decode(0xF100),
+            Err(DecodeError::UnknownOpcode(0xF100))
+        );
+        assert_eq!(known, 44_586);
+    }
+
+    #[test]
+    fn reads_long_instructions() {
+        let code = [0xF0, 0x00, 0x12, 0x34, 0x60, 0x01];
+        assert_eq!(Instruction::read(&code), Ok(Instruction::LdILong(0x1234)));
+        assert_eq!(
+            Instruction::read(&code[4..]),
+            Ok(Instruction::LdByte { x: 0, nn: 1 })
+        );
+        assert_eq!(Instruction::LdILong(0x1234).to_bytes(), code[..4]);
+        assert_eq!(Instruction::LdILong(0x1234).size(), 4);
+    }
+
+    #[test]
+    fn reports_truncated_instructions() {
+        assert_eq!(Instruction::read(&[]), Err(DecodeError::Truncated));
+        assert_eq!(Instruction::read(&[0x60]), Err(DecodeError::Truncated));
+        assert_eq!(
+            Instruction::read(&[0xF0, 0x00]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Instruction::read(&[0xF0, 0x00, 0x12]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            Instruction::read(&[0x50, 0x01]),
+            Err(DecodeError::UnknownOpcode(0x5001))
+        );
+    }
+}
@@ -1,12 +1,67 @@
 #![warn(clippy::pedantic, clippy::all)]
 #![feature(stmt_expr_attributes)]
 
-use rand::random;
-
+mod audio;
+mod cpu_state;
+pub mod debugger;
+mod dirty;
+pub mod disasm;
+mod error;
+mod frame;
+mod inspect;
+mod instruction;
+mod palette;
+mod platform;
+mod quirks;
+mod render;
+mod rewind;
+mod rng;
+mod scheduler;
+pub mod state;
+pub mod trace;
+
+pub use audio::{Beeper, Waveform};
+pub use cpu_state::CpuState;
+pub use debugger::Debugger;
+pub use dirty::DirtyRect;
+pub use error::{LoadError, VmError};
+pub use frame::FrameReport;
+pub use inspect::VmSnapshot;
+pub use instruction::{DecodeError, Instruction};
+pub use palette::Palette;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use render::Renderer;
+pub use rewind::Rewind;
+pub use rng::{RandomSource, SplitMix64};
+pub use scheduler::Scheduler;
+pub use state::StateError;
+pub use trace::{ParseTraceError, TraceLine};
+
+use debugger::{Access, MemoryAccess};
+use error::Fault;
+use std::cell::OnceCell;
+use trace::Trace;
+
+type Result<T> = std::result::Result<T, Fault>;
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Vm {
     pc: u16,
-    memory: [u8; Self::MEMORY_SIZE],
-    display: [bool; Self::SCREEN_WIDTH * Self::SCREEN_HEIGHT],
+    memory: Vec<u8>,
+    display: [[u128; Self::MAX_SCREEN_HEIGHT]; Self::PLANE_COUNT],
+    /// Changed parts of the display, see `dirty_rects`.
+    dirty: Vec<DirtyRect>,
+    /// Set whenever the display is marked dirty, `run_frame` clears it to tell
+    /// whether the frame changed the display.
+    display_touched: bool,
+    /// Pixels of each plane unpacked by `get_plane`, dropped when the display
+    /// is marked dirty.
+    unpacked: [OnceCell<Vec<bool>>; Self::PLANE_COUNT],
+    hires: bool,
+    planes: u8,
+    audio_pattern: [u8; Self::AUDIO_PATTERN_SIZE],
+    pitch: u8,
     v_reg: [u8; Self::REG_COUNT],
     i_reg: u16,
     stack: [u16; Self::STACK_SIZE],
@@ -14,18 +69,58 @@ pub struct Vm {
     delay_timer: u8,
     sound_timer: u8,
     keys: [bool; Self::KEYS_COUNT],
+    rpl: [u8; Self::RPL_COUNT],
+    error: Option<VmError>,
+    exited: bool,
+    platform: Platform,
+    quirks: Quirks,
+    vblank_wait: bool,
+    key_wait: Option<KeyWait>,
+    /// PC of the breakpoint a `Debugger` stopped before, until it runs.
+    breakpoint: Option<u16>,
+    rng: Box<dyn RandomSource>,
+    access_log: Option<Vec<MemoryAccess>>,
+    decode_cache: Option<Vec<Option<Instruction>>>,
+    trace: Option<Trace>,
+    load_addr: u16,
+}
+
+/// An FX0A in progress.
+#[derive(Debug, Clone, Copy)]
+struct KeyWait {
+    reg: u8,
+    /// The key pressed since, which completes FX0A once released.
+    key: Option<u8>,
 }
 
 // --- Constants ---
 impl Vm {
-    pub const SCREEN_WIDTH: usize = 0x40;
-    pub const SCREEN_HEIGHT: usize = 0x20;
-
-    const MEMORY_SIZE: usize = 4 * 1024;
-    const REG_COUNT: usize = 16;
-    const STACK_SIZE: usize = 16;
-    const KEYS_COUNT: usize = 16;
-    const START_ADDR: u16 = 0x200;
+    /// Width of the high resolution display, the largest a program can use.
+    pub const MAX_SCREEN_WIDTH: usize = 0x80;
+    /// Height of the high resolution display, the largest a program can use.
+    pub const MAX_SCREEN_HEIGHT: usize = 0x40;
+
+    const LORES_WIDTH: usize = 0x40;
+    const LORES_HEIGHT: usize = 0x20;
+    const DISPLAY_SIZE: usize = Self::MAX_SCREEN_WIDTH * Self::MAX_SCREEN_HEIGHT;
+    /// Number of bitplanes, only XO-CHIP programs can draw to the second one.
+    pub const PLANE_COUNT: usize = 2;
+
+    /// Size of the XO-CHIP audio pattern buffer, in bytes.
+    pub const AUDIO_PATTERN_SIZE: usize = 16;
+    const DEFAULT_PITCH: u8 = 64;
+    /// Number of V registers, V0 to VF.
+    pub const REG_COUNT: usize = 16;
+    /// Number of SUPER-CHIP RPL user flags.
+    pub const RPL_COUNT: usize = 16;
+    /// Number of return addresses the stack holds.
+    pub const STACK_SIZE: usize = 16;
+    /// Number of keys on the hex keypad.
+    pub const KEYS_COUNT: usize = 16;
+    /// Where programs are loaded by default, as on the COSMAC VIP.
+    pub const START_ADDR: u16 = 0x200;
+    /// Where the ETI-660 loads programs, see `set_load_address`.
+    pub const ETI_660_START_ADDR: u16 = 0x600;
 
     const FONT_SET_SIZE: usize = 80;
     const FONT_SET: [u8; Self::FONT_SET_SIZE] = [
@@ -46,16 +141,48 @@ impl Vm {
         0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
         0xF0, 0x80, 0xF0, 0x80, 0x80, // F
     ];
+
+    const BIG_FONT_ADDR: usize = Self::FONT_SET_SIZE;
+    const BIG_FONT_SET_SIZE: usize = 160;
+    const BIG_FONT_SET: [u8; Self::BIG_FONT_SET_SIZE] = [
+        0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+        0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+        0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+        0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+    ];
 }
 
 impl Default for Vm {
     fn default() -> Self {
-        let mut memory = [0; Self::MEMORY_SIZE];
-        memory[..Self::FONT_SET_SIZE].copy_from_slice(&Self::FONT_SET);
         Self {
             pc: Self::START_ADDR,
-            memory,
-            display: [false; Self::SCREEN_WIDTH * Self::SCREEN_HEIGHT],
+            memory: Self::initial_memory(Platform::default().memory_size()),
+            display: [[0; Self::MAX_SCREEN_HEIGHT]; Self::PLANE_COUNT],
+            // nothing was drawn yet
+            dirty: vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: Self::LORES_WIDTH,
+                height: Self::LORES_HEIGHT,
+            }],
+            display_touched: true,
+            unpacked: Default::default(),
+            hires: false,
+            planes: 1,
+            audio_pattern: [0; Self::AUDIO_PATTERN_SIZE],
+            pitch: Self::DEFAULT_PITCH,
             v_reg: [0; Self::REG_COUNT],
             i_reg: 0,
             stack: [0; Self::STACK_SIZE],
@@ -63,18 +190,104 @@ impl Default for Vm {
             delay_timer: 0,
             sound_timer: 0,
             keys: [false; Self::KEYS_COUNT],
+            rpl: [0; Self::RPL_COUNT],
+            error: None,
+            exited: false,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            vblank_wait: false,
+            key_wait: None,
+            breakpoint: None,
+            rng: Box::new(SplitMix64::new(rand::random())),
+            access_log: None,
+            decode_cache: None,
+            trace: None,
+            load_addr: Self::START_ADDR,
         }
     }
 }
 
 // --- Public Methods ---
 impl Vm {
+    /// Creates a Vm whose CXNN results are fully determined by `seed`.
+    #[must_use]
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: Box::new(SplitMix64::new(seed)),
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the random source used by CXNN, e.g. to replay recorded values.
+    pub fn set_random_source(&mut self, rng: impl RandomSource + 'static) {
+        self.rng = Box::new(rng);
+    }
+
+    /// Returns the state of the random source, see `RandomSource::state`.
+    #[must_use]
+    pub fn random_state(&self) -> u64 {
+        self.rng.state()
+    }
+
+    /// Restores the state of the random source, see `RandomSource::set_state`.
+    pub fn set_random_state(&mut self, state: u64) {
+        self.rng.set_state(state);
+    }
+
+    /// Creates a Vm that interprets the ambiguous opcodes according to `quirks`.
+    #[must_use]
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self {
+            quirks,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Changes the quirks, taking effect from the next executed opcode.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Creates a Vm emulating `platform`, using the quirks that platform expects.
+    #[must_use]
+    pub fn with_platform(platform: Platform) -> Self {
+        Self {
+            memory: Self::initial_memory(platform.memory_size()),
+            platform,
+            quirks: platform.quirks(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// Switches to `platform` and its quirks, then resets the Vm.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.quirks = platform.quirks();
+        self.reset();
+    }
+
     /// Resets the Vm to its initial state without creating new object.
+    ///
+    /// The RPL user flags survive a reset, like they did on the HP48.
     pub fn reset(&mut self) {
-        self.pc = Self::START_ADDR;
-        self.memory = [0; Self::MEMORY_SIZE];
-        self.memory[..Self::FONT_SET_SIZE].copy_from_slice(&Self::FONT_SET);
-        self.display = [false; Self::SCREEN_WIDTH * Self::SCREEN_HEIGHT];
+        self.pc = self.load_addr;
+        self.memory = Self::initial_memory(self.platform.memory_size());
+        self.display = [[0; Self::MAX_SCREEN_HEIGHT]; Self::PLANE_COUNT];
+        self.hires = false;
+        self.mark_display_dirty();
+        self.planes = 1;
+        self.audio_pattern = [0; Self::AUDIO_PATTERN_SIZE];
+        self.pitch = Self::DEFAULT_PITCH;
         self.v_reg = [0; Self::REG_COUNT];
         self.i_reg = 0;
         self.stack = [0; Self::STACK_SIZE];
@@ -82,40 +295,284 @@ impl Vm {
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.keys = [false; Self::KEYS_COUNT];
+        self.error = None;
+        self.exited = false;
+        self.vblank_wait = false;
+        self.key_wait = None;
+        self.breakpoint = None;
+        self.clear_decode_cache();
     }
 
-    /// Load program bytes into the Vm memory, starting at addr 0x200.
-    pub fn load_program(&mut self, data: &[u8]) {
-        let start = usize::from(Self::START_ADDR);
-        let end = usize::from(Self::START_ADDR) + data.len();
-        self.memory[start..end].copy_from_slice(data);
+    /// Loads a program at the load address and points the Program Counter at it.
+    ///
+    /// Whatever a previous program left in memory is cleared, as is a pending
+    /// FX0A, the rest of the Vm is left as it is. On XO-CHIP the program can fill the memory up to 0xFFFF.
+    ///
+    /// # Errors
+    ///
+    /// Returns `LoadError::RomTooLarge` if the program does not fit between the
+    /// load address and the end of memory, leaving the Vm untouched.
+    pub fn load_program(&mut self, data: &[u8]) -> std::result::Result<(), LoadError> {
+        let start = usize::from(self.load_addr);
+        let max = self.memory.len().saturating_sub(start);
+        if data.len() > max {
+            return Err(LoadError::RomTooLarge {
+                size: data.len(),
+                max,
+                platform: self.platform,
+            });
+        }
+
+        self.memory = Self::initial_memory(self.memory.len());
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.pc = self.load_addr;
+        self.key_wait = None;
+        self.clear_decode_cache();
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn load_address(&self) -> u16 {
+        self.load_addr
+    }
+
+    /// Changes where `load_program` puts programs and `reset` starts them,
+    /// e.g. to `ETI_660_START_ADDR`.
+    pub fn set_load_address(&mut self, addr: u16) {
+        self.load_addr = addr;
+    }
+
+    /// Keeps every decoded instruction, keyed by its address, so running the
+    /// same code again skips the fetch and decode steps.
+    ///
+    /// Instructions are dropped from the cache when an opcode writes over them,
+    /// so self-modifying programs keep working. Disabled by default.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| vec![None; self.memory.len()]);
+    }
+
+    #[must_use]
+    pub fn has_decode_cache(&self) -> bool {
+        self.decode_cache.is_some()
     }
 
+    /// Returns the pixels of the display, row by row, `screen_width` pixels per row.
+    ///
+    /// This is the first bitplane, the only one CHIP-8 and SUPER-CHIP programs use.
+    /// It is unpacked from `get_rows` on the first call after the display
+    /// changed, prefer that in hot loops.
     #[must_use]
     pub fn get_display(&self) -> &[bool] {
-        &self.display
+        self.get_plane(0)
+    }
+
+    /// Returns the pixels of a single bitplane, laid out like `get_display`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `plane` is not lower than `PLANE_COUNT`.
+    #[must_use]
+    pub fn get_plane(&self, plane: usize) -> &[bool] {
+        self.unpacked[plane].get_or_init(|| self.pixels(plane).collect())
+    }
+
+    /// Returns the rows of a single bitplane, `screen_height` of them.
+    ///
+    /// Pixel `x` of a row is bit `screen_width - 1 - x`, the leftmost pixel being
+    /// the highest bit in use; in low resolution only the lower 64 bits are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `plane` is not lower than `PLANE_COUNT`.
+    #[must_use]
+    pub fn get_rows(&self, plane: usize) -> &[u128] {
+        &self.display[plane][..self.screen_height()]
+    }
+
+    /// Returns the color of every pixel, laid out like `get_display`.
+    ///
+    /// Bit 0 of a color is set by the first bitplane and bit 1 by the second,
+    /// giving the four colors of XO-CHIP.
+    pub fn get_colors(&self) -> impl Iterator<Item = u8> + '_ {
+        self.pixels(0)
+            .zip(self.pixels(1))
+            .map(|(first, second)| u8::from(first) | (u8::from(second) << 1))
     }
 
+    /// Width of the display in the current resolution.
+    #[must_use]
+    pub fn screen_width(&self) -> usize {
+        if self.hires {
+            Self::MAX_SCREEN_WIDTH
+        } else {
+            Self::LORES_WIDTH
+        }
+    }
+
+    /// Height of the display in the current resolution.
+    #[must_use]
+    pub fn screen_height(&self) -> usize {
+        if self.hires {
+            Self::MAX_SCREEN_HEIGHT
+        } else {
+            Self::LORES_HEIGHT
+        }
+    }
+
+    /// Returns `true` while the SUPER-CHIP high resolution mode is on.
+    #[must_use]
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Returns `true` while the buzzer sounds, i.e. the sound timer is non-zero.
+    #[must_use]
+    pub fn is_sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Returns the XO-CHIP audio pattern, one bit per sample.
+    #[must_use]
+    pub fn audio_pattern(&self) -> &[u8; Self::AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    /// Returns the XO-CHIP pitch register, 64 meaning a playback rate of 4000 Hz.
+    #[must_use]
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Presses or releases key `idx`, releasing the key pressed while FX0A
+    /// waits completes it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is above 0xF.
+    #[allow(clippy::cast_possible_truncation)]
     pub fn keypress(&mut self, idx: usize, pressed: bool) {
         self.keys[idx] = pressed;
+
+        let Some(wait) = &mut self.key_wait else {
+            return;
+        };
+        let key = idx as u8;
+        match wait.key {
+            None if pressed => wait.key = Some(key),
+            Some(waited) if waited == key && !pressed => {
+                self.v_reg[usize::from(wait.reg)] = key;
+                self.key_wait = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the error that halted the Vm, if any.
+    #[must_use]
+    pub fn error(&self) -> Option<VmError> {
+        self.error
+    }
+
+    /// Returns `true` if the program exited (00FD) or an error halted the Vm.
+    /// Only `reset` resumes it.
+    #[must_use]
+    pub fn is_halted(&self) -> bool {
+        self.exited || self.error.is_some()
+    }
+
+    /// Returns what the Vm does on the next tick.
+    #[must_use]
+    pub fn cpu_state(&self) -> CpuState {
+        if self.is_halted() {
+            CpuState::Halted
+        } else if self.breakpoint.is_some() {
+            CpuState::Breakpoint
+        } else if let Some(wait) = self.key_wait {
+            CpuState::WaitingForKey { reg: wait.reg }
+        } else {
+            CpuState::Running
+        }
     }
 
     /// Read and execute a single Opcode.
+    ///
+    /// Errors are not reported; they halt the Vm and can be queried through `error`.
     pub fn tick(&mut self) {
-        let opcode = self.fetch_next_opcode();
-        self.execute_opcode(opcode);
+        let _ = self.try_tick();
+    }
+
+    /// Read and execute a single Opcode, reporting any error.
+    ///
+    /// On error the Vm is halted with `pc` pointing at the faulting instruction,
+    /// and every following call returns the same error until `reset`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `VmError` if the opcode is unknown, overflows or underflows the
+    /// stack, accesses memory out of bounds or refers to a non-existent key.
+    pub fn try_tick(&mut self) -> std::result::Result<(), VmError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.exited || self.vblank_wait || self.key_wait.is_some() {
+            return Ok(());
+        }
+
+        self.breakpoint = None;
+        self.trace_instruction();
+        let pc = self.pc;
+        let result = self.fetch_instruction().and_then(|instruction| {
+            self.execute(instruction)
+                .map_err(|fault| fault.at(pc, instruction.encode()))
+        });
+
+        if let Err(error) = result {
+            self.pc = pc;
+            self.error = Some(error);
+        }
+        result
+    }
+
+    /// Runs a 60 Hz frame: up to `cycles` instructions, then the timers.
+    ///
+    /// The frame ends early when the Vm halts, and stops executing when it
+    /// waits for a key or the display, the timers still tick then.
+    pub fn run_frame(&mut self, cycles: u32) -> FrameReport {
+        self.display_touched = false;
+
+        let mut instructions = 0;
+        while instructions < cycles {
+            match self.cpu_state() {
+                CpuState::Running | CpuState::Breakpoint if !self.vblank_wait => {}
+                _ => break,
+            }
+            if self.try_tick().is_err() {
+                break;
+            }
+            instructions += 1;
+        }
+        if !self.is_halted() {
+            self.tick_timers();
+        }
+
+        FrameReport {
+            instructions,
+            display_changed: self.display_touched,
+            sound_active: self.is_sound_active(),
+            waiting_for_key: matches!(self.cpu_state(), CpuState::WaitingForKey { .. }),
+            error: self.error,
+        }
     }
 
     /// Updates the delay and sound timers.
     pub fn tick_timers(&mut self) {
+        self.vblank_wait = false;
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                // TODO: Emit sound
-            }
             self.sound_timer -= 1;
         }
     }
@@ -123,381 +580,1497 @@ impl Vm {
 
 // --- Private Methods ---
 impl Vm {
+    /// Returns the memory of a freshly reset Vm, holding only the fonts.
+    fn initial_memory(size: usize) -> Vec<u8> {
+        let mut memory = vec![0; size];
+        memory[..Self::FONT_SET_SIZE].copy_from_slice(&Self::FONT_SET);
+        memory[Self::BIG_FONT_ADDR..Self::BIG_FONT_ADDR + Self::BIG_FONT_SET_SIZE]
+            .copy_from_slice(&Self::BIG_FONT_SET);
+        memory
+    }
+
     /// Pushes an address to the stack.
-    fn push_stack(&mut self, addr: u16) {
-        self.stack[self.sp as usize] = addr;
+    fn push_stack(&mut self, addr: u16) -> Result<()> {
+        let slot = self
+            .stack
+            .get_mut(self.sp as usize)
+            .ok_or(Fault::StackOverflow)?;
+        *slot = addr;
         self.sp += 1;
+        Ok(())
     }
 
     /// Pops an address from the stack and returns the last address.
-    fn pop_stack(&mut self) -> u16 {
-        self.sp -= 1;
-        self.stack[self.sp as usize]
+    fn pop_stack(&mut self) -> Result<u16> {
+        self.sp = self.sp.checked_sub(1).ok_or(Fault::StackUnderflow)?;
+        Ok(self.stack[self.sp as usize])
+    }
+
+    /// Returns the memory range `addr..addr + len`, or a fault if it does not fit.
+    fn memory_range(&self, addr: usize, len: usize) -> Result<std::ops::Range<usize>> {
+        if addr + len > self.memory.len() {
+            let first_invalid = addr.max(self.memory.len());
+            return Err(Fault::MemoryOutOfBounds(first_invalid));
+        }
+        Ok(addr..addr + len)
+    }
+
+    /// Returns the range an opcode is about to read from, see `memory_range`.
+    fn readable(&mut self, addr: usize, len: usize) -> Result<std::ops::Range<usize>> {
+        let range = self.memory_range(addr, len)?;
+        self.log_access(&range, Access::Read);
+        Ok(range)
+    }
+
+    /// Returns the range an opcode is about to write to, see `memory_range`.
+    fn writable(&mut self, addr: usize, len: usize) -> Result<std::ops::Range<usize>> {
+        let range = self.memory_range(addr, len)?;
+        self.log_access(&range, Access::Write);
+        self.invalidate_decode_cache(&range);
+        Ok(range)
+    }
+
+    /// Drops the cached instructions overlapping `range`, and the memory
+    /// checksum of the trace, before it is written to.
+    fn invalidate_decode_cache(&mut self, range: &std::ops::Range<usize>) {
+        self.forget_memory_crc();
+        if let Some(cache) = &mut self.decode_cache {
+            // an instruction is up to 4 bytes long, so one starting up to 3
+            // bytes before the range can be overwritten too
+            let first = range.start.saturating_sub(3);
+            cache[first..range.end].fill(None);
+        }
+    }
+
+    /// Empties the decode cache, and the memory checksum of the trace, after
+    /// the whole memory changed.
+    pub(crate) fn clear_decode_cache(&mut self) {
+        self.forget_memory_crc();
+        if let Some(cache) = &mut self.decode_cache {
+            cache.clear();
+            cache.resize(self.memory.len(), None);
+        }
+    }
+
+    /// Records a data access for the debugger's watchpoints, when it asked for them.
+    fn log_access(&mut self, range: &std::ops::Range<usize>, access: Access) {
+        if let Some(log) = &mut self.access_log {
+            log.push(MemoryAccess {
+                range: range.clone(),
+                access,
+            });
+        }
+    }
+
+    /// Returns the instruction at the Program Counter and moves past it, from
+    /// the decode cache when it has it.
+    fn fetch_instruction(&mut self) -> std::result::Result<Instruction, VmError> {
+        let pc = self.pc;
+        let cache = self.decode_cache.as_ref();
+        if let Some(instruction) = cache.and_then(|cache| cache.get(pc as usize)?.as_ref()) {
+            let instruction = *instruction;
+            // an instruction is 2 or 4 bytes long
+            #[allow(clippy::cast_possible_truncation)]
+            let size = instruction.size() as u16;
+            self.pc = pc.wrapping_add(size);
+            return Ok(instruction);
+        }
+
+        let opcode = self.fetch_next_opcode().map_err(|fault| fault.at(pc, 0))?;
+        let instruction = self.decode(opcode).map_err(|fault| fault.at(pc, opcode))?;
+        if let Some(cache) = &mut self.decode_cache {
+            cache[pc as usize] = Some(instruction);
+        }
+        Ok(instruction)
     }
 
     /// Returns a single Opcode, based on the current Program Counter.
-    fn fetch_next_opcode(&mut self) -> u16 {
-        let higher_byte = u16::from(self.memory[self.pc as usize]);
-        let lower_byte = u16::from(self.memory[self.pc as usize + 1]);
-        let opcode = (higher_byte << 8) | lower_byte;
-        self.pc += 2;
-        opcode
+    fn fetch_next_opcode(&mut self) -> Result<u16> {
+        let range = self.memory_range(self.pc as usize, 2)?;
+        let bytes = &self.memory[range];
+        let opcode = (u16::from(bytes[0]) << 8) | u16::from(bytes[1]);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(opcode)
+    }
+
+    /// Skips the next instruction, which is 4 bytes long if it is XO-CHIP's F000 NNNN.
+    fn skip_next_instruction(&mut self) {
+        let pc = self.pc as usize;
+        let long =
+            self.platform.has_xo_chip() && self.memory.get(pc..pc + 2) == Some(&[0xF0, 0x00]);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
     }
 
-    /// Executes a single Opcode.
-    fn execute_opcode(&mut self, opcode: u16) {
-        let digit_1 = (opcode & 0xF000) >> 12;
-        let digit_2 = (opcode & 0x0F00) >> 8;
-        let digit_3 = (opcode & 0x00F0) >> 4;
-        let digit_4 = opcode & 0x000F;
+    /// Unpacks the rows of `plane` into pixels, laid out like `get_display`.
+    fn pixels(&self, plane: usize) -> impl Iterator<Item = bool> + '_ {
+        let width = self.screen_width();
+        self.get_rows(plane)
+            .iter()
+            .flat_map(move |row| (0..width).rev().map(move |bit| row & (1 << bit) != 0))
+    }
+
+    /// Mask of the bits a row uses in the current resolution.
+    fn row_mask(&self) -> u128 {
+        u128::MAX >> (Self::MAX_SCREEN_WIDTH - self.screen_width())
+    }
+
+    /// Returns `true` if `plane` is selected for drawing, clearing and scrolling.
+    fn is_plane_selected(&self, plane: usize) -> bool {
+        self.planes & (1 << plane) != 0
+    }
 
+    /// Returns the pressed state of the key named by `vx`.
+    fn key(&self, vx: u8) -> Result<bool> {
+        self.keys
+            .get(vx as usize)
+            .copied()
+            .ok_or(Fault::InvalidKey(vx))
+    }
+
+    /// Decodes `opcode` for the current platform, fetching the address that
+    /// follows F000.
+    fn decode(&mut self, opcode: u16) -> Result<Instruction> {
+        let instruction = Instruction::decode(opcode).map_err(|_| Fault::UnknownOpcode)?;
+        if !self.platform.supports(instruction) {
+            return Err(Fault::UnknownOpcode);
+        }
+        match instruction {
+            Instruction::LdILong(_) => Ok(Instruction::LdILong(self.fetch_next_opcode()?)),
+            _ => Ok(instruction),
+        }
+    }
+
+    /// Executes a single Instruction.
+    fn execute(&mut self, instruction: Instruction) -> Result<()> {
         #[rustfmt::skip]
-        match (digit_1, digit_2, digit_3, digit_4) {
-            (0x0, 0x0, 0x0, 0x0) => { /* Do nothing */ }
-            (0x0, 0x0, 0xE, 0x0) => self.clear_display(),
-            (0x0, 0x0, 0xE, 0xE) => self.return_from_subroutine(),
-            (0x1,   _,   _,   _) => self.jump_to_address(opcode),
-            (0x2,   _,   _,   _) => self.call_subroutine(opcode),
-            (0x3,   _,   _,   _) => self.skip_if_vx_equal_nn(digit_2, opcode),
-            (0x4,   _,   _,   _) => self.skip_if_vx_not_equal_nn(digit_2, opcode),
-            (0x5,   _,   _, 0x0) => self.skip_if_vx_equals_vy(digit_2, digit_3),
-            (0x6,   _,   _,   _) => self.set_vx_to_nn(digit_2, opcode),
-            (0x7,   _,   _,   _) => self.increment_vx_by_nn(digit_2, opcode),
-            (0x8,   _,   _, 0x0) => self.set_vx_to_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x1) => self.set_vx_to_bit_or_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x2) => self.set_vx_to_bit_and_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x3) => self.set_vx_to_bit_xor_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x4) => self.increment_vx_by_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x5) => self.decrement_vx_by_vy(digit_2, digit_3),
-            (0x8,   _,   _, 0x6) => self.right_shift_vx(digit_2),
-            (0x8,   _,   _, 0x7) => self.set_vx_to_vy_minus_vx(digit_2, digit_3),
-            (0x8,   _,   _, 0xE) => self.left_shift_vx(digit_2),
-            (0x9,   _,   _, 0x0) => self.skip_if_vx_not_equal_vy(digit_2, digit_3),
-            (0xA,   _,   _,   _) => self.set_i_to_nnn(opcode),
-            (0xB,   _,   _,   _) => self.jump_v0_plus_nnn(opcode),
-            (0xC,   _,   _,   _) => self.set_vx_to_bit_and_rand_nn(digit_2, opcode),
-            (0xD,   _,   _,   _) => self.draw_sprite(digit_2, digit_3, digit_4),
-            (0xE,   _, 0x9, 0xE) => self.skip_if_key_pressed(digit_2),
-            (0xE,   _, 0xA, 0x1) => self.skip_if_key_not_pressed(digit_2),
-            (0xF,   _, 0x0, 0x7) => self.set_vx_to_dt(digit_2),
-            (0xF,   _, 0x0, 0xA) => self.wait_key_press(digit_2),
-            (0xF,   _, 0x1, 0x5) => self.set_dt_to_vx(digit_2),
-            (0xF,   _, 0x1, 0x8) => self.set_st_to_vx(digit_2),
-            (0xF,   _, 0x1, 0xE) => self.increment_i_by_vx(digit_2),
-            (0xF,   _, 0x2, 0x9) => self.set_i_to_font_address(digit_2),
-            (0xF,   _, 0x3, 0x3) => self.load_i_bcd_vx(digit_2),
-            (0xF,   _, 0x5, 0x5) => self.store_v0_vx_into_i(digit_2),
-            (0xF,   _, 0x6, 0x5) => self.load_i_into_v0_vx(digit_2),
-            (  _,   _,   _,   _) => {
-                unimplemented!("Unimplemented opcode: ({:#x}) at pc: ({:#x})", opcode, self.pc)
+        match instruction {
+            Instruction::Nop                 => { /* Do nothing */ }
+            Instruction::ScrollDown(n)       => self.scroll_down(n),
+            Instruction::ScrollUp(n)         => self.scroll_up(n),
+            Instruction::Cls                 => self.clear_display(),
+            Instruction::Ret                 => self.return_from_subroutine()?,
+            Instruction::ScrollRight         => self.scroll_right(),
+            Instruction::ScrollLeft          => self.scroll_left(),
+            Instruction::Exit                => self.exit(),
+            Instruction::Low                 => self.set_resolution(false),
+            Instruction::High                => self.set_resolution(true),
+            Instruction::Jp(nnn)             => self.jump_to_address(nnn),
+            Instruction::Call(nnn)           => self.call_subroutine(nnn)?,
+            Instruction::SeByte { x, nn }    => self.skip_if_vx_equal_nn(x, nn),
+            Instruction::SneByte { x, nn }   => self.skip_if_vx_not_equal_nn(x, nn),
+            Instruction::SeReg { x, y }      => self.skip_if_vx_equals_vy(x, y),
+            Instruction::SaveRange { x, y }  => self.store_vx_vy_into_i(x, y)?,
+            Instruction::LoadRange { x, y }  => self.load_i_into_vx_vy(x, y)?,
+            Instruction::LdByte { x, nn }    => self.set_vx_to_nn(x, nn),
+            Instruction::AddByte { x, nn }   => self.increment_vx_by_nn(x, nn),
+            Instruction::LdReg { x, y }      => self.set_vx_to_vy(x, y),
+            Instruction::Or { x, y }         => self.set_vx_to_bit_or_vy(x, y),
+            Instruction::And { x, y }        => self.set_vx_to_bit_and_vy(x, y),
+            Instruction::Xor { x, y }        => self.set_vx_to_bit_xor_vy(x, y),
+            Instruction::AddReg { x, y }     => self.increment_vx_by_vy(x, y),
+            Instruction::Sub { x, y }        => self.decrement_vx_by_vy(x, y),
+            Instruction::Shr { x, y }        => self.right_shift_vx(x, y),
+            Instruction::Subn { x, y }       => self.set_vx_to_vy_minus_vx(x, y),
+            Instruction::Shl { x, y }        => self.left_shift_vx(x, y),
+            Instruction::SneReg { x, y }     => self.skip_if_vx_not_equal_vy(x, y),
+            Instruction::LdI(nnn)            => self.set_i_to_nnn(nnn),
+            Instruction::JpV0(nnn)           => self.jump_v0_plus_nnn(nnn),
+            Instruction::Rnd { x, nn }       => self.set_vx_to_bit_and_rand_nn(x, nn),
+            Instruction::Drw { x, y, n }     => self.draw_sprite(x, y, n)?,
+            Instruction::Skp(x)              => self.skip_if_key_pressed(x)?,
+            Instruction::Sknp(x)             => self.skip_if_key_not_pressed(x)?,
+            Instruction::LdILong(nnnn)       => self.set_i_to_long_nnnn(nnnn),
+            Instruction::Plane(n)            => self.select_planes(n),
+            Instruction::Audio               => self.load_audio_pattern()?,
+            Instruction::LdVxDt(x)           => self.set_vx_to_dt(x),
+            Instruction::LdVxK(x)            => self.wait_key_press(x),
+            Instruction::LdDtVx(x)           => self.set_dt_to_vx(x),
+            Instruction::LdStVx(x)           => self.set_st_to_vx(x),
+            Instruction::AddIVx(x)           => self.increment_i_by_vx(x),
+            Instruction::LdF(x)              => self.set_i_to_font_address(x),
+            Instruction::LdHf(x)             => self.set_i_to_big_font_address(x),
+            Instruction::LdB(x)              => self.load_i_bcd_vx(x)?,
+            Instruction::Pitch(x)            => self.set_pitch_to_vx(x),
+            Instruction::LdIVx(x)            => self.store_v0_vx_into_i(x)?,
+            Instruction::LdVxI(x)            => self.load_i_into_v0_vx(x)?,
+            Instruction::LdRVx(x)            => self.store_v0_vx_into_rpl(x),
+            Instruction::LdVxR(x)            => self.load_rpl_into_v0_vx(x),
+        }
+
+        Ok(())
+    }
+
+    /// 00CN
+    fn scroll_down(&mut self, n: u8) {
+        let height = self.screen_height();
+        let shift = usize::from(n);
+        for plane in 0..Self::PLANE_COUNT {
+            if self.is_plane_selected(plane) {
+                self.display[plane].copy_within(..height - shift, shift);
+                self.display[plane][..shift].fill(0);
+            }
+        }
+        self.mark_display_dirty();
+    }
+
+    /// 00DN
+    fn scroll_up(&mut self, n: u8) {
+        let height = self.screen_height();
+        let shift = usize::from(n);
+        for plane in 0..Self::PLANE_COUNT {
+            if self.is_plane_selected(plane) {
+                self.display[plane].copy_within(shift..height, 0);
+                self.display[plane][height - shift..height].fill(0);
             }
         }
+        self.mark_display_dirty();
     }
 
     /// 00E0
     fn clear_display(&mut self) {
-        self.display = [false; Self::SCREEN_WIDTH * Self::SCREEN_HEIGHT];
+        for plane in 0..Self::PLANE_COUNT {
+            if self.is_plane_selected(plane) {
+                self.display[plane] = [0; Self::MAX_SCREEN_HEIGHT];
+            }
+        }
+        self.mark_display_dirty();
     }
 
     /// 00EE
-    fn return_from_subroutine(&mut self) {
-        self.pc = self.pop_stack();
+    fn return_from_subroutine(&mut self) -> Result<()> {
+        self.pc = self.pop_stack()?;
+        Ok(())
+    }
+
+    /// 00FB, scrolls by 4 pixels of the current resolution.
+    fn scroll_right(&mut self) {
+        let height = self.screen_height();
+        for plane in 0..Self::PLANE_COUNT {
+            if !self.is_plane_selected(plane) {
+                continue;
+            }
+            for row in &mut self.display[plane][..height] {
+                *row >>= 4;
+            }
+        }
+        self.mark_display_dirty();
+    }
+
+    /// 00FC, scrolls by 4 pixels of the current resolution.
+    fn scroll_left(&mut self) {
+        let height = self.screen_height();
+        let mask = self.row_mask();
+        for plane in 0..Self::PLANE_COUNT {
+            if !self.is_plane_selected(plane) {
+                continue;
+            }
+            for row in &mut self.display[plane][..height] {
+                *row = (*row << 4) & mask;
+            }
+        }
+        self.mark_display_dirty();
+    }
+
+    /// 00FD
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    /// 00FE (lores) and 00FF (hires), switching also clears every bitplane.
+    fn set_resolution(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = [[0; Self::MAX_SCREEN_HEIGHT]; Self::PLANE_COUNT];
+        self.mark_display_dirty();
     }
 
     /// 1NNN
-    fn jump_to_address(&mut self, opcode: u16) {
-        let nnn = opcode & 0xFFF;
+    fn jump_to_address(&mut self, nnn: u16) {
         self.pc = nnn;
     }
 
     /// 2NNN
-    fn call_subroutine(&mut self, opcode: u16) {
-        let nnn = opcode & 0xFFF;
-        self.push_stack(self.pc);
+    fn call_subroutine(&mut self, nnn: u16) -> Result<()> {
+        self.push_stack(self.pc)?;
         self.pc = nnn;
+        Ok(())
     }
 
     /// 3XNN
-    #[allow(clippy::cast_possible_truncation)]
-    fn skip_if_vx_equal_nn(&mut self, digit_2: u16, opcode: u16) {
-        let x = digit_2 as usize;
-        let nn = (opcode & 0xFF) as u8;
+    fn skip_if_vx_equal_nn(&mut self, x: u8, nn: u8) {
+        let x = usize::from(x);
         if self.v_reg[x] == nn {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
     /// 4XNN
-    #[allow(clippy::cast_possible_truncation)]
-    fn skip_if_vx_not_equal_nn(&mut self, digit_2: u16, opcode: u16) {
-        let x = digit_2 as usize;
-        let nn = (opcode & 0xFF) as u8;
+    fn skip_if_vx_not_equal_nn(&mut self, x: u8, nn: u8) {
+        let x = usize::from(x);
         if self.v_reg[x] != nn {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
     /// 5XY0
-    fn skip_if_vx_equals_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn skip_if_vx_equals_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         if self.v_reg[x] == self.v_reg[y] {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
+    /// 5XY2, stores VX..=VY (or VX down to VY) at I, leaving I untouched.
+    fn store_vx_vy_into_i(&mut self, x: u8, y: u8) -> Result<()> {
+        let x = usize::from(x);
+        let y = usize::from(y);
+        let range = self.writable(self.i_reg as usize, x.abs_diff(y) + 1)?;
+        if x <= y {
+            self.memory[range].copy_from_slice(&self.v_reg[x..=y]);
+        } else {
+            for (addr, reg) in range.zip((y..=x).rev()) {
+                self.memory[addr] = self.v_reg[reg];
+            }
+        }
+        Ok(())
+    }
+
+    /// 5XY3, loads VX..=VY (or VX down to VY) from I, leaving I untouched.
+    fn load_i_into_vx_vy(&mut self, x: u8, y: u8) -> Result<()> {
+        let x = usize::from(x);
+        let y = usize::from(y);
+        let range = self.readable(self.i_reg as usize, x.abs_diff(y) + 1)?;
+        if x <= y {
+            self.v_reg[x..=y].copy_from_slice(&self.memory[range]);
+        } else {
+            for (addr, reg) in range.zip((y..=x).rev()) {
+                self.v_reg[reg] = self.memory[addr];
+            }
+        }
+        Ok(())
+    }
+
     /// 6XNN
-    #[allow(clippy::cast_possible_truncation)]
-    fn set_vx_to_nn(&mut self, digit_2: u16, opcode: u16) {
-        let x = digit_2 as usize;
-        let nn = (opcode & 0xFF) as u8;
+    fn set_vx_to_nn(&mut self, x: u8, nn: u8) {
+        let x = usize::from(x);
         self.v_reg[x] = nn;
     }
 
     /// 7XNN
-    #[allow(clippy::cast_possible_truncation)]
-    fn increment_vx_by_nn(&mut self, digit_2: u16, opcode: u16) {
-        let x = digit_2 as usize;
-        let nn = (opcode & 0xFF) as u8;
+    fn increment_vx_by_nn(&mut self, x: u8, nn: u8) {
+        let x = usize::from(x);
         self.v_reg[x] = self.v_reg[x].wrapping_add(nn);
     }
 
     /// 8XY0
-    fn set_vx_to_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn set_vx_to_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         self.v_reg[x] = self.v_reg[y];
     }
 
     /// 8XY1
-    fn set_vx_to_bit_or_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn set_vx_to_bit_or_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         self.v_reg[x] |= self.v_reg[y];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
 
     /// 8XY2
-    fn set_vx_to_bit_and_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn set_vx_to_bit_and_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         self.v_reg[x] &= self.v_reg[y];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
 
     /// 8XY3
-    fn set_vx_to_bit_xor_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn set_vx_to_bit_xor_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         self.v_reg[x] ^= self.v_reg[y];
+        if self.quirks.vf_reset {
+            self.v_reg[0xF] = 0;
+        }
     }
 
     /// 8XY4
-    fn increment_vx_by_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn increment_vx_by_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
 
         let (new_v_x, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
-        let new_v_f = if carry { 1 } else { 0 };
+        let new_v_f = u8::from(carry);
 
         self.v_reg[x] = new_v_x;
         self.v_reg[0xF] = new_v_f;
     }
 
     /// 8XY5
-    fn decrement_vx_by_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn decrement_vx_by_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
 
         let (new_v_x, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
-        let new_v_f = if borrow { 0 } else { 1 };
+        let new_v_f = u8::from(!borrow);
 
         self.v_reg[x] = new_v_x;
         self.v_reg[0xF] = new_v_f;
     }
 
     /// 8XY6
-    fn right_shift_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
-        let lsb = self.v_reg[x] & 1;
-        self.v_reg[x] >>= 1;
+    fn right_shift_vx(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = if self.quirks.shift { x } else { usize::from(y) };
+        let lsb = self.v_reg[y] & 1;
+        self.v_reg[x] = self.v_reg[y] >> 1;
         self.v_reg[0xF] = lsb;
     }
 
     /// 8XY7
-    fn set_vx_to_vy_minus_vx(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn set_vx_to_vy_minus_vx(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
 
         let (new_v_x, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
-        let new_v_f = if borrow { 0 } else { 1 };
+        let new_v_f = u8::from(!borrow);
 
         self.v_reg[x] = new_v_x;
         self.v_reg[0xF] = new_v_f;
     }
 
     /// 8XYE
-    fn left_shift_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
-        let msb = (self.v_reg[x] >> 7) & 1;
-        self.v_reg[x] <<= 1;
+    fn left_shift_vx(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = if self.quirks.shift { x } else { usize::from(y) };
+        let msb = (self.v_reg[y] >> 7) & 1;
+        self.v_reg[x] = self.v_reg[y] << 1;
         self.v_reg[0xF] = msb;
     }
 
     /// 9XY0
-    fn skip_if_vx_not_equal_vy(&mut self, digit_2: u16, digit_3: u16) {
-        let x = digit_2 as usize;
-        let y = digit_3 as usize;
+    fn skip_if_vx_not_equal_vy(&mut self, x: u8, y: u8) {
+        let x = usize::from(x);
+        let y = usize::from(y);
         if self.v_reg[x] != self.v_reg[y] {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
     }
 
     /// ANNN
-    fn set_i_to_nnn(&mut self, opcode: u16) {
-        let nnn = opcode & 0xFFF;
+    fn set_i_to_nnn(&mut self, nnn: u16) {
         self.i_reg = nnn;
     }
 
-    /// BNNN
-    fn jump_v0_plus_nnn(&mut self, opcode: u16) {
-        let nnn = opcode & 0xFFF;
-        self.pc = u16::from(self.v_reg[0]) + nnn;
+    /// BNNN (or BXNN with the jump quirk)
+    fn jump_v0_plus_nnn(&mut self, nnn: u16) {
+        let x = if self.quirks.jump {
+            usize::from(nnn >> 8)
+        } else {
+            0
+        };
+        self.pc = u16::from(self.v_reg[x]) + nnn;
     }
 
     /// CXNN
-    #[allow(clippy::cast_possible_truncation)]
-    fn set_vx_to_bit_and_rand_nn(&mut self, digit_2: u16, opcode: u16) {
-        let x = digit_2 as usize;
-        let nn = (opcode & 0xFF) as u8;
-        let rng = random::<u8>();
+    fn set_vx_to_bit_and_rand_nn(&mut self, x: u8, nn: u8) {
+        let x = usize::from(x);
+        let rng = self.rng.next_byte();
         self.v_reg[x] = rng & nn;
     }
 
-    /// DXYN
-    fn draw_sprite(&mut self, digit_2: u16, digit_3: u16, digit_4: u16) {
-        let x_coord = u16::from(self.v_reg[digit_2 as usize]);
-        let y_coord = u16::from(self.v_reg[digit_3 as usize]);
-        let num_rows = digit_4;
+    /// DXYN (and DXY0 drawing a 16x16 sprite on SUPER-CHIP)
+    ///
+    /// XO-CHIP draws the sprite on every selected plane, reading the data of
+    /// the second plane right after the data of the first one.
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<()> {
+        let width = self.screen_width();
+        let height = self.screen_height();
+
+        // the starting position always wraps, only the sprite itself may be clipped
+        let x_coord = usize::from(self.v_reg[usize::from(x)]) % width;
+        let y_coord = usize::from(self.v_reg[usize::from(y)]) % height;
+        let (num_cols, num_rows) = if n == 0 && self.platform.has_super_chip() {
+            (16, 16)
+        } else {
+            (8, usize::from(n))
+        };
+        let bytes_per_row = num_cols / 8;
+        let sprite_size = num_rows * bytes_per_row;
+
+        let selected_planes = self.planes.count_ones() as usize;
+        let sprites = self.readable(self.i_reg as usize, selected_planes * sprite_size)?;
 
         let mut flipped = false;
-        for y_line in 0..num_rows {
-            let addr = self.i_reg + y_line;
-            let pixels = self.memory[addr as usize];
+        let mut sprite_start = sprites.start;
+        for plane in 0..Self::PLANE_COUNT {
+            if !self.is_plane_selected(plane) {
+                continue;
+            }
 
-            for x_line in 0..8 {
-                if (pixels & (0b_1000_0000 >> x_line)) != 0 {
-                    let x = usize::from(x_coord + x_line) % Self::SCREEN_WIDTH;
-                    let y = usize::from(y_coord + y_line) % Self::SCREEN_HEIGHT;
+            for y_line in 0..num_rows {
+                let y = y_coord + y_line;
+                if self.quirks.clipping && y >= height {
+                    break;
+                }
 
-                    let idx = x + Self::SCREEN_WIDTH * y;
+                let row_start = sprite_start + y_line * bytes_per_row;
+                let pixels = self.memory[row_start..row_start + bytes_per_row]
+                    .iter()
+                    .fold(0_u128, |row, byte| (row << 8) | u128::from(*byte));
+                let sprite_row = self.sprite_row(pixels, num_cols, x_coord);
 
-                    flipped |= self.display[idx];
-                    self.display[idx] ^= true;
-                }
+                let row = &mut self.display[plane][y % height];
+                flipped |= *row & sprite_row != 0;
+                *row ^= sprite_row;
             }
+            sprite_start += sprite_size;
         }
 
-        self.v_reg[0xF] = if flipped { 1 } else { 0 };
+        if selected_planes > 0 {
+            self.mark_sprite_dirty(x_coord, y_coord, num_cols, num_rows);
+        }
+        self.v_reg[0xF] = u8::from(flipped);
+        self.vblank_wait = self.quirks.display_wait;
+        Ok(())
+    }
+
+    /// Places the `num_cols` pixels of a sprite row at column `x` of a display
+    /// row, wrapping around or clipping what goes past the right edge.
+    fn sprite_row(&self, pixels: u128, num_cols: usize, x: usize) -> u128 {
+        let width = self.screen_width();
+        let end = x + num_cols;
+        if end <= width {
+            return pixels << (width - end);
+        }
+
+        let overflow = end - width;
+        let visible = pixels >> overflow;
+        if self.quirks.clipping {
+            visible
+        } else {
+            visible | ((pixels << (width - overflow)) & self.row_mask())
+        }
     }
 
     /// EX9E
-    fn skip_if_key_pressed(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn skip_if_key_pressed(&mut self, x: u8) -> Result<()> {
+        let x = usize::from(x);
         let vx = self.v_reg[x];
-        let key = self.keys[vx as usize];
+        let key = self.key(vx)?;
         if key {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
+        Ok(())
     }
 
     /// EXA1
-    fn skip_if_key_not_pressed(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn skip_if_key_not_pressed(&mut self, x: u8) -> Result<()> {
+        let x = usize::from(x);
         let vx = self.v_reg[x];
-        let key = self.keys[vx as usize];
+        let key = self.key(vx)?;
         if !key {
-            self.pc += 2;
+            self.skip_next_instruction();
         }
+        Ok(())
+    }
+
+    /// F000 NNNN
+    fn set_i_to_long_nnnn(&mut self, nnnn: u16) {
+        self.i_reg = nnnn;
+    }
+
+    /// FN01
+    fn select_planes(&mut self, n: u8) {
+        self.planes = n & 0x3;
+    }
+
+    /// F002
+    fn load_audio_pattern(&mut self) -> Result<()> {
+        let range = self.readable(self.i_reg as usize, Self::AUDIO_PATTERN_SIZE)?;
+        self.audio_pattern.copy_from_slice(&self.memory[range]);
+        Ok(())
     }
 
     /// FX07
-    fn set_vx_to_dt(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn set_vx_to_dt(&mut self, x: u8) {
+        let x = usize::from(x);
         self.v_reg[x] = self.delay_timer;
     }
 
     /// FX0A
+    ///
+    /// Like the COSMAC VIP, a key held already counts as pressed, and the
+    /// instruction only completes once it is released, see `keypress`.
     #[allow(clippy::cast_possible_truncation)]
-    fn wait_key_press(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
-        let mut pressed = false;
-        for i in 0..self.keys.len() {
-            if self.keys[i] {
-                self.v_reg[x] = i as u8;
-                pressed = true;
-                break;
-            }
-        }
-        if !pressed {
-            self.pc -= 2;
-        }
+    fn wait_key_press(&mut self, x: u8) {
+        let key = self.keys.iter().position(|&pressed| pressed);
+        self.key_wait = Some(KeyWait {
+            reg: x,
+            key: key.map(|key| key as u8),
+        });
     }
 
     /// FX15
-    fn set_dt_to_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn set_dt_to_vx(&mut self, x: u8) {
+        let x = usize::from(x);
         self.delay_timer = self.v_reg[x];
     }
 
     /// FX18
-    fn set_st_to_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn set_st_to_vx(&mut self, x: u8) {
+        let x = usize::from(x);
         self.sound_timer = self.v_reg[x];
     }
 
     /// FX1E
-    fn increment_i_by_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn increment_i_by_vx(&mut self, x: u8) {
+        let x = usize::from(x);
         let vx = u16::from(self.v_reg[x]);
         self.i_reg = self.i_reg.wrapping_add(vx);
     }
 
     /// FX29
-    fn set_i_to_font_address(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn set_i_to_font_address(&mut self, x: u8) {
+        let x = usize::from(x);
         let c = u16::from(self.v_reg[x]);
         self.i_reg = c * 5;
     }
 
+    /// FX30
+    #[allow(clippy::cast_possible_truncation)]
+    fn set_i_to_big_font_address(&mut self, x: u8) {
+        let x = usize::from(x);
+        let c = u16::from(self.v_reg[x]);
+        self.i_reg = Self::BIG_FONT_ADDR as u16 + c * 10;
+    }
+
     /// FX33
-    fn load_i_bcd_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
+    fn load_i_bcd_vx(&mut self, x: u8) -> Result<()> {
+        let x = usize::from(x);
         let vx = self.v_reg[x];
 
         let hundreds = vx / 100;
         let tens = (vx - hundreds * 100) / 10;
         let ones = vx - hundreds * 100 - tens * 10;
 
-        let i = self.i_reg as usize;
-        self.memory[i] = hundreds;
-        self.memory[i + 1] = tens;
-        self.memory[i + 2] = ones;
+        let range = self.writable(self.i_reg as usize, 3)?;
+        self.memory[range].copy_from_slice(&[hundreds, tens, ones]);
+        Ok(())
+    }
+
+    /// FX3A
+    fn set_pitch_to_vx(&mut self, x: u8) {
+        let x = usize::from(x);
+        self.pitch = self.v_reg[x];
     }
 
     /// FX55
-    fn store_v0_vx_into_i(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
-        let i = self.i_reg as usize;
-        for idx in 0..=x {
-            self.memory[i + idx] = self.v_reg[idx];
-        }
+    fn store_v0_vx_into_i(&mut self, x: u8) -> Result<()> {
+        let x = usize::from(x);
+        let range = self.writable(self.i_reg as usize, x + 1)?;
+        self.memory[range].copy_from_slice(&self.v_reg[..=x]);
+        self.increment_i_after_load_store(x);
+        Ok(())
     }
 
     /// FX65
-    fn load_i_into_v0_vx(&mut self, digit_2: u16) {
-        let x = digit_2 as usize;
-        let i = self.i_reg as usize;
-        for idx in 0..=x {
-            self.v_reg[idx] = self.memory[i + idx];
+    fn load_i_into_v0_vx(&mut self, x: u8) -> Result<()> {
+        let x = usize::from(x);
+        let range = self.readable(self.i_reg as usize, x + 1)?;
+        self.v_reg[..=x].copy_from_slice(&self.memory[range]);
+        self.increment_i_after_load_store(x);
+        Ok(())
+    }
+
+    /// Moves I past the registers V0..=VX with the load/store quirk.
+    #[allow(clippy::cast_possible_truncation)]
+    fn increment_i_after_load_store(&mut self, x: usize) {
+        if self.quirks.load_store {
+            self.i_reg = self.i_reg.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    /// FX75
+    fn store_v0_vx_into_rpl(&mut self, x: u8) {
+        let x = usize::from(x);
+        self.rpl[..=x].copy_from_slice(&self.v_reg[..=x]);
+    }
+
+    /// FX85
+    fn load_rpl_into_v0_vx(&mut self, x: u8) {
+        let x = usize::from(x);
+        self.v_reg[..=x].copy_from_slice(&self.rpl[..=x]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a Vm of `platform` with `program` loaded.
+    fn vm_on(platform: Platform, program: &[u8]) -> Vm {
+        let mut vm = Vm::with_platform(platform);
+        vm.load_program(program).unwrap();
+        vm
+    }
+
+    fn vm(program: &[u8]) -> Vm {
+        vm_on(Platform::Chip8, program)
+    }
+
+    /// Returns a CHIP-8 Vm with `program` loaded, following `quirks`.
+    fn vm_with(quirks: Quirks, program: &[u8]) -> Vm {
+        let mut vm = vm(program);
+        vm.set_quirks(quirks);
+        vm
+    }
+
+    /// Ticks `vm` `count` times, panicking on the first error.
+    fn run(vm: &mut Vm, count: usize) {
+        for _ in 0..count {
+            vm.try_tick().unwrap();
+        }
+    }
+
+    #[test]
+    fn vm_is_send() {
+        fn is_send<T: Send>() {}
+        is_send::<Vm>();
+    }
+
+    #[test]
+    fn get_display_follows_the_display() {
+        let mut vm = Vm::default();
+        // draw the font's "0" at 0, 0 then clear the screen
+        vm.load_program(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x00, 0xE0])
+            .unwrap();
+        assert_eq!(vm.get_display().len(), Vm::LORES_WIDTH * Vm::LORES_HEIGHT);
+        assert!(!vm.get_display().contains(&true));
+
+        for _ in 0..3 {
+            vm.tick();
+        }
+        let drawn: Vec<_> = vm.get_display()[..4].to_vec();
+        assert_eq!(drawn, [true; 4]);
+        assert!(vm.get_display()[Vm::LORES_WIDTH]);
+        assert_eq!(vm.get_display(), vm.get_plane(0));
+        assert!(!vm.get_plane(1).contains(&true));
+
+        vm.tick();
+        assert!(!vm.get_display().contains(&true));
+    }
+
+    #[test]
+    fn reports_unknown_opcodes() {
+        let mut vm = vm(&[0x50, 0x01]);
+        let error = VmError::UnknownOpcode {
+            pc: 0x200,
+            opcode: 0x5001,
+        };
+        assert_eq!(vm.try_tick(), Err(error));
+        assert_eq!(vm.error(), Some(error));
+        assert_eq!(vm.pc(), 0x200);
+
+        // XO-CHIP opcodes are unknown to the other platforms
+        let mut vm = vm_on(Platform::SuperChip, &[0x50, 0x12]);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x5012,
+            })
+        );
+    }
+
+    #[test]
+    fn reports_stack_overflows() {
+        // call 0x200
+        let mut vm = vm(&[0x22, 0x00]);
+        run(&mut vm, Vm::STACK_SIZE);
+        assert_eq!(vm.sp(), Vm::STACK_SIZE);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200,
+            })
+        );
+        assert_eq!(vm.sp(), Vm::STACK_SIZE);
+    }
+
+    #[test]
+    fn reports_stack_underflows() {
+        let mut vm = vm(&[0x00, 0xEE]);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE,
+            })
+        );
+        assert_eq!(vm.sp(), 0);
+    }
+
+    #[test]
+    fn reports_fetches_past_the_memory() {
+        // jump 0xFFF, where only one byte of the opcode is left
+        let mut vm = vm(&[0x1F, 0xFF]);
+        run(&mut vm, 1);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::MemoryOutOfBounds {
+                pc: 0xFFF,
+                opcode: 0,
+                addr: 0x1000,
+            })
+        );
+        assert_eq!(vm.pc(), 0xFFF);
+    }
+
+    #[test]
+    fn reports_accesses_past_the_memory() {
+        // i := 0xFFE, save v2
+        let mut vm = vm(&[0xAF, 0xFE, 0xF2, 0x55]);
+        vm.set_v_reg(0, 1);
+        run(&mut vm, 1);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::MemoryOutOfBounds {
+                pc: 0x202,
+                opcode: 0xF255,
+                addr: 0x1000,
+            })
+        );
+        // nothing was written
+        assert_eq!(vm.memory()[0xFFE..], [0, 0]);
+
+        // i := 0xFFE, bcd v0
+        let mut vm = self::vm(&[0xAF, 0xFE, 0xF0, 0x33]);
+        run(&mut vm, 1);
+        assert!(matches!(
+            vm.try_tick(),
+            Err(VmError::MemoryOutOfBounds { addr: 0x1000, .. })
+        ));
+    }
+
+    #[test]
+    fn reports_invalid_keys() {
+        // v0 := 0x10, if v0 key
+        let mut vm = vm(&[0x60, 0x10, 0xE0, 0x9E]);
+        run(&mut vm, 1);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::InvalidKey {
+                pc: 0x202,
+                opcode: 0xE09E,
+                key: 0x10,
+            })
+        );
+    }
+
+    #[test]
+    fn stays_halted_until_reset() {
+        // v0 := 1, return
+        let mut vm = vm(&[0x60, 0x01, 0x00, 0xEE]);
+        let error = VmError::StackUnderflow {
+            pc: 0x202,
+            opcode: 0x00EE,
+        };
+        run(&mut vm, 1);
+        for _ in 0..3 {
+            assert_eq!(vm.try_tick(), Err(error));
+            assert_eq!(vm.pc(), 0x202);
+            assert!(vm.is_halted());
+            assert_eq!(vm.cpu_state(), CpuState::Halted);
         }
+        vm.tick();
+        assert_eq!(vm.error(), Some(error));
+
+        let report = vm.run_frame(10);
+        assert_eq!(report.instructions, 0);
+        assert_eq!(report.error, Some(error));
+
+        vm.reset();
+        assert_eq!(vm.error(), None);
+        assert!(!vm.is_halted());
+        assert_eq!(vm.cpu_state(), CpuState::Running);
+        assert_eq!(vm.try_tick(), Ok(()));
+        assert_eq!(vm.pc(), 0x202);
+    }
+
+    /// Runs `script` on a Vm with the decode cache and on one without, which
+    /// have to end up in the same state, and returns the one with the cache.
+    fn with_and_without_cache(platform: Platform, program: &[u8], script: impl Fn(&mut Vm)) -> Vm {
+        let mut uncached = vm_on(platform, program);
+        let mut cached = vm_on(platform, program);
+        cached.set_decode_cache(true);
+        script(&mut uncached);
+        script(&mut cached);
+        assert_eq!(cached.snapshot(), uncached.snapshot());
+        cached
+    }
+
+    #[test]
+    fn decodes_code_written_by_fx55_again() {
+        let program = [
+            0xA2, 0x06, // i := 0x206
+            0xF1, 0x55, // save v1
+            0x12, 0x04, // jump 0x204
+            0x6A, 0xFF, // va := 0xFF, overwritten with va := 0x12
+        ];
+        let vm = with_and_without_cache(Platform::Chip8, &program, |vm| {
+            vm.set_pc(0x206);
+            run(vm, 1);
+            assert_eq!(vm.v_reg(0xA), 0xFF);
+            vm.set_v_reg(0, 0x6A);
+            vm.set_v_reg(1, 0x12);
+            vm.set_pc(0x200);
+            run(vm, 2);
+            vm.set_pc(0x206);
+            run(vm, 1);
+        });
+        assert_eq!(vm.v_reg(0xA), 0x12);
+    }
+
+    #[test]
+    fn decodes_code_written_by_fx33_again() {
+        let program = [
+            0xA2, 0x07, // i := 0x207
+            0xF0, 0x33, // bcd v0
+            0x12, 0x04, // jump 0x204
+            0x6A, 0xFF, // va := 0xFF, its second byte overwritten with 0
+            0x12, 0x08, // jump 0x208
+        ];
+        let vm = with_and_without_cache(Platform::Chip8, &program, |vm| {
+            vm.set_pc(0x206);
+            run(vm, 1);
+            assert_eq!(vm.v_reg(0xA), 0xFF);
+            vm.set_v_reg(0, 7);
+            vm.set_pc(0x200);
+            run(vm, 2);
+            vm.set_pc(0x206);
+            run(vm, 1);
+        });
+        assert_eq!(vm.memory()[0x206..0x20A], [0x6A, 0, 0, 7]);
+        assert_eq!(vm.v_reg(0xA), 0);
+    }
+
+    #[test]
+    fn decodes_code_written_by_5xy2_again() {
+        let program = [
+            0xA2, 0x08, // i := 0x208
+            0x51, 0x22, // save v1 - v2
+            0x12, 0x04, // jump 0x204
+            0xF0, 0x00, 0x12, 0x34, // i := long 0x1234, its address overwritten
+        ];
+        let vm = with_and_without_cache(Platform::XoChip, &program, |vm| {
+            vm.set_pc(0x206);
+            run(vm, 1);
+            assert_eq!(vm.i_reg(), 0x1234);
+            vm.set_v_reg(1, 0x0A);
+            vm.set_v_reg(2, 0xBC);
+            vm.set_pc(0x200);
+            run(vm, 2);
+            vm.set_pc(0x206);
+            run(vm, 1);
+        });
+        assert_eq!(vm.i_reg(), 0x0ABC);
+        assert_eq!(vm.pc(), 0x20A);
+    }
+
+    #[test]
+    fn runs_self_modifying_code_the_same_with_the_decode_cache() {
+        let program = [
+            0xA2, 0x07, // i := 0x207
+            0xF0, 0x65, // load v0
+            0x70, 0x03, // v0 += 3
+            0x6A, 0x00, // va := 0, its second byte rewritten on every loop
+            0xF0, 0x55, // save v0
+            0x8D, 0xA4, // vd += va
+            0x12, 0x00, // jump 0x200
+        ];
+        let mut uncached = vm(&program);
+        let mut cached = vm(&program);
+        cached.set_decode_cache(true);
+        for _ in 0..500 {
+            run(&mut uncached, 1);
+            run(&mut cached, 1);
+            assert_eq!(cached.snapshot(), uncached.snapshot());
+        }
+        assert_ne!(cached.v_reg(0xA), 0);
+    }
+
+    /// v3 := key, v1 := 1
+    const WAIT_KEY: [u8; 4] = [0xF3, 0x0A, 0x61, 0x01];
+
+    #[test]
+    fn waits_for_a_key_press_and_release() {
+        let mut vm = vm(&WAIT_KEY);
+        assert_eq!(vm.cpu_state(), CpuState::Running);
+        run(&mut vm, 1);
+        assert_eq!(vm.cpu_state(), CpuState::WaitingForKey { reg: 3 });
+
+        // ticking does nothing meanwhile
+        run(&mut vm, 3);
+        assert_eq!(vm.pc(), 0x202);
+        assert_eq!(vm.v_reg(1), 0);
+        let report = vm.run_frame(10);
+        assert_eq!(report.instructions, 0);
+        assert!(report.waiting_for_key);
+
+        vm.keypress(5, true);
+        assert_eq!(vm.cpu_state(), CpuState::WaitingForKey { reg: 3 });
+        assert_eq!(vm.v_reg(3), 0);
+        vm.keypress(5, false);
+        assert_eq!(vm.cpu_state(), CpuState::Running);
+        assert_eq!(vm.v_reg(3), 5);
+
+        run(&mut vm, 1);
+        assert_eq!(vm.v_reg(1), 1);
+    }
+
+    #[test]
+    fn waits_for_the_release_of_the_pressed_key() {
+        let mut vm = vm(&WAIT_KEY);
+        run(&mut vm, 1);
+        vm.keypress(0xC, false);
+        vm.keypress(5, true);
+        vm.keypress(7, true);
+        vm.keypress(7, false);
+        assert_eq!(vm.cpu_state(), CpuState::WaitingForKey { reg: 3 });
+        vm.keypress(5, false);
+        assert_eq!(vm.cpu_state(), CpuState::Running);
+        assert_eq!(vm.v_reg(3), 5);
+    }
+
+    #[test]
+    fn counts_a_held_key_as_pressed() {
+        let mut vm = vm(&WAIT_KEY);
+        vm.keypress(9, true);
+        run(&mut vm, 1);
+        assert_eq!(vm.cpu_state(), CpuState::WaitingForKey { reg: 3 });
+
+        vm.keypress(2, true);
+        vm.keypress(2, false);
+        assert_eq!(vm.cpu_state(), CpuState::WaitingForKey { reg: 3 });
+        vm.keypress(9, false);
+        assert_eq!(vm.cpu_state(), CpuState::Running);
+        assert_eq!(vm.v_reg(3), 9);
+    }
+
+    #[test]
+    fn shifts_vx_or_vy() {
+        let program = [
+            0x60, 0x40, // v0 := 0x40
+            0x61, 0x03, // v1 := 3
+            0x80, 0x16, // v0 >>= v1
+            0x62, 0x81, // v2 := 0x81
+            0x83, 0x2E, // v3 <<= v2
+        ];
+        let shift = |shift| {
+            let mut vm = vm_with(
+                Quirks {
+                    shift,
+                    ..Quirks::CHIMP
+                },
+                &program,
+            );
+            run(&mut vm, 3);
+            let right = (vm.v_reg(0), vm.v_reg(0xF));
+            run(&mut vm, 2);
+            (right, (vm.v_reg(3), vm.v_reg(0xF)))
+        };
+        assert_eq!(shift(true), ((0x20, 0), (0, 0)));
+        assert_eq!(shift(false), ((1, 1), (2, 1)));
+    }
+
+    #[test]
+    fn moves_i_after_load_and_store() {
+        let program = [
+            0x60, 0x0A, // v0 := 0x0A
+            0x61, 0x0B, // v1 := 0x0B
+            0xA3, 0x00, // i := 0x300
+            0xF1, 0x55, // save v1
+            0x60, 0x00, // v0 := 0
+            0x61, 0x00, // v1 := 0
+            0xA3, 0x00, // i := 0x300
+            0xF1, 0x65, // load v1
+        ];
+        let load_store = |load_store| {
+            let mut vm = vm_with(
+                Quirks {
+                    load_store,
+                    ..Quirks::CHIMP
+                },
+                &program,
+            );
+            run(&mut vm, 4);
+            assert_eq!(vm.memory()[0x300..0x302], [0x0A, 0x0B]);
+            let stored = vm.i_reg();
+            run(&mut vm, 4);
+            assert_eq!(vm.v_regs()[..2], [0x0A, 0x0B]);
+            (stored, vm.i_reg())
+        };
+        assert_eq!(load_store(true), (0x302, 0x302));
+        assert_eq!(load_store(false), (0x300, 0x300));
+    }
+
+    #[test]
+    fn jumps_with_v0_or_vx() {
+        let program = [
+            0x60, 0x05, // v0 := 5
+            0x62, 0x10, // v2 := 0x10
+            0xB2, 0x34, // jump0 0x234
+        ];
+        let jump = |jump| {
+            let mut vm = vm_with(
+                Quirks {
+                    jump,
+                    ..Quirks::CHIMP
+                },
+                &program,
+            );
+            run(&mut vm, 3);
+            vm.pc()
+        };
+        assert_eq!(jump(true), 0x244);
+        assert_eq!(jump(false), 0x239);
+    }
+
+    #[test]
+    fn resets_vf_after_logic_opcodes() {
+        for (opcode, result) in [(0x11, 0x0F), (0x12, 0x04), (0x13, 0x0B)] {
+            let program = [
+                0x60, 0x0C, // v0 := 0x0C
+                0x61, 0x07, // v1 := 0x07
+                0x6F, 0x05, // vf := 5
+                0x80, opcode, // v0 |= v1, v0 &= v1 or v0 ^= v1
+            ];
+            let vf_reset = |vf_reset| {
+                let mut vm = vm_with(
+                    Quirks {
+                        vf_reset,
+                        ..Quirks::CHIMP
+                    },
+                    &program,
+                );
+                run(&mut vm, 4);
+                assert_eq!(vm.v_reg(0), result);
+                vm.v_reg(0xF)
+            };
+            assert_eq!(vf_reset(true), 0);
+            assert_eq!(vf_reset(false), 5);
+        }
+    }
+
+    #[test]
+    fn clips_or_wraps_sprites() {
+        let program = [
+            0x60, 0x3C, // v0 := 60
+            0x61, 0x1E, // v1 := 30
+            0xA2, 0x0A, // i := sprite
+            0xD0, 0x14, // sprite v0 v1 4
+            0x12, 0x08, // jump 0x208
+            0xFF, 0xFF, 0xFF, 0xFF, // sprite
+        ];
+        let clipping = |clipping| {
+            let mut vm = vm_with(
+                Quirks {
+                    clipping,
+                    ..Quirks::CHIMP
+                },
+                &program,
+            );
+            run(&mut vm, 4);
+            assert_eq!(vm.v_reg(0xF), 0);
+            vm.get_rows(0).to_vec()
+        };
+
+        let rows = clipping(true);
+        assert_eq!(rows[30..], [0xF, 0xF]);
+        assert!(rows[..30].iter().all(|&row| row == 0));
+
+        let rows = clipping(false);
+        let wrapped = 0xF000_0000_0000_000F;
+        assert_eq!(rows[30..], [wrapped, wrapped]);
+        assert_eq!(rows[..2], [wrapped, wrapped]);
+        assert!(rows[2..30].iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn waits_for_the_display() {
+        let program = [
+            0xD0, 0x01, // sprite v0 v0 1
+            0x70, 0x01, // v0 += 1
+            0x12, 0x02, // jump 0x202
+        ];
+        let display_wait = |display_wait| {
+            let mut vm = vm_with(
+                Quirks {
+                    display_wait,
+                    ..Quirks::CHIMP
+                },
+                &program,
+            );
+            vm.run_frame(10).instructions
+        };
+        assert_eq!(display_wait(true), 1);
+        assert_eq!(display_wait(false), 10);
+    }
+
+    /// Draws a pixel at the top left corner, then runs `opcode`.
+    fn draw_then(opcode: [u8; 2]) -> Vm {
+        let program = [
+            0xA2, 0x08, // i := 0x208
+            0xD0, 0x01, // sprite v0 v0 1
+            opcode[0], opcode[1], 0x12, 0x06, // jump 0x206
+            0x80, // sprite
+        ];
+        let mut vm = vm_on(Platform::SuperChip, &program);
+        run(&mut vm, 2);
+        assert_eq!(vm.get_rows(0)[0], 1 << 63);
+        vm
+    }
+
+    #[test]
+    fn scrolls_down() {
+        let mut vm = draw_then([0x00, 0xC2]);
+        run(&mut vm, 1);
+        assert_eq!(vm.get_rows(0)[..3], [0, 0, 1 << 63]);
+    }
+
+    #[test]
+    fn scrolls_right_and_left() {
+        let mut vm = draw_then([0x00, 0xFB]);
+        run(&mut vm, 1);
+        assert_eq!(vm.get_rows(0)[0], 1 << 59);
+
+        // scrolling left drops the pixels leaving the screen
+        let mut vm = draw_then([0x00, 0xFC]);
+        run(&mut vm, 1);
+        assert!(vm.get_rows(0).iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn switches_the_resolution() {
+        let mut vm = draw_then([0x00, 0xFF]);
+        run(&mut vm, 1);
+        assert!(vm.is_hires());
+        assert_eq!((vm.screen_width(), vm.screen_height()), (128, 64));
+        assert!(!vm.get_display().contains(&true));
+
+        let mut vm = draw_then([0x00, 0xFE]);
+        run(&mut vm, 1);
+        assert!(!vm.is_hires());
+        assert_eq!((vm.screen_width(), vm.screen_height()), (64, 32));
+        assert!(!vm.get_display().contains(&true));
+    }
+
+    #[test]
+    fn draws_16x16_sprites() {
+        let mut program = vec![
+            0x00, 0xFF, // hires
+            0xA2, 0x0A, // i := sprite
+            0xD0, 0x00, // sprite v0 v0 0
+            0xD0, 0x00, // sprite v0 v0 0
+            0x12, 0x08, // jump 0x208
+        ];
+        program.extend([0xFF; 32]);
+        let mut vm = vm_on(Platform::SuperChip, &program);
+        run(&mut vm, 3);
+        let rows = vm.get_rows(0);
+        assert!(rows[..16].iter().all(|&row| row == 0xFFFF << 112));
+        assert!(rows[16..].iter().all(|&row| row == 0));
+        assert_eq!(vm.v_reg(0xF), 0);
+
+        run(&mut vm, 1);
+        assert!(vm.get_rows(0).iter().all(|&row| row == 0));
+        assert_eq!(vm.v_reg(0xF), 1);
+
+        // CHIP-8 draws nothing for DXY0
+        let mut vm = self::vm(&[0xD0, 0x00]);
+        run(&mut vm, 1);
+        assert!(vm.get_rows(0).iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn points_i_at_the_big_font() {
+        // v0 := 7, bighex v0
+        let mut vm = vm_on(Platform::SuperChip, &[0x60, 0x07, 0xF0, 0x30]);
+        run(&mut vm, 2);
+        let i = usize::from(vm.i_reg());
+        assert_eq!(i, Vm::BIG_FONT_ADDR + 70);
+        assert_eq!(vm.memory()[i..i + 10], Vm::BIG_FONT_SET[70..80]);
+    }
+
+    #[test]
+    fn saves_and_loads_the_rpl_flags() {
+        let program = [
+            0x60, 0x01, // v0 := 1
+            0x61, 0x02, // v1 := 2
+            0x62, 0x03, // v2 := 3
+            0xF1, 0x75, // saveflags v1
+            0x60, 0x00, // v0 := 0
+            0x61, 0x00, // v1 := 0
+            0x62, 0x00, // v2 := 0
+            0xF2, 0x85, // loadflags v2
+        ];
+        let mut vm = vm_on(Platform::SuperChip, &program);
+        run(&mut vm, 4);
+        assert_eq!(vm.rpl()[..3], [1, 2, 0]);
+        run(&mut vm, 4);
+        assert_eq!(vm.v_regs()[..3], [1, 2, 0]);
+
+        vm.reset();
+        assert_eq!(vm.rpl()[..3], [1, 2, 0]);
+    }
+
+    #[test]
+    fn draws_on_the_selected_planes() {
+        let program = [
+            0xA2, 0x0C, // i := sprites
+            0xF2, 0x01, // plane 2
+            0xD0, 0x01, // sprite v0 v0 1
+            0xF3, 0x01, // plane 3
+            0xD0, 0x01, // sprite v0 v0 1
+            0x12, 0x0A, // jump 0x20A
+            0xC0, 0xA0, // sprites of the first and second plane
+        ];
+        let mut vm = vm_on(Platform::XoChip, &program);
+        run(&mut vm, 3);
+        assert_eq!(vm.planes(), 2);
+        assert_eq!(vm.get_rows(0)[0], 0);
+        assert_eq!(vm.get_rows(1)[0], 0xC0 << 56);
+
+        run(&mut vm, 2);
+        assert_eq!(vm.planes(), 3);
+        assert_eq!(vm.get_rows(0)[0], 0xC0 << 56);
+        assert_eq!(vm.get_rows(1)[0], 0x60 << 56);
+        let colors: Vec<_> = vm.get_colors().take(4).collect();
+        assert_eq!(colors, [1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn saves_and_loads_register_ranges() {
+        let program = [
+            0xA3, 0x00, // i := 0x300
+            0x51, 0x32, // save v1 - v3
+            0xA3, 0x10, // i := 0x310
+            0x53, 0x12, // save v3 - v1
+            0x54, 0x63, // load v4 - v6
+            0x5A, 0x83, // load va - v8
+        ];
+        let mut vm = vm_on(Platform::XoChip, &program);
+        for (x, value) in [(1, 1), (2, 2), (3, 3)] {
+            vm.set_v_reg(x, value);
+        }
+        run(&mut vm, 2);
+        assert_eq!(vm.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(vm.i_reg(), 0x300);
+        run(&mut vm, 2);
+        assert_eq!(vm.memory()[0x310..0x313], [3, 2, 1]);
+        assert_eq!(vm.i_reg(), 0x310);
+        run(&mut vm, 2);
+        assert_eq!(vm.v_regs()[4..=6], [3, 2, 1]);
+        assert_eq!(vm.v_regs()[8..=0xA], [1, 2, 3]);
+        assert_eq!(vm.i_reg(), 0x310);
+    }
+
+    #[test]
+    fn loads_long_addresses() {
+        let program = [
+            0xF0, 0x00, 0xFF, 0xF0, // i := long 0xFFF0
+            0xF1, 0x65, // load v1
+        ];
+        let mut vm = vm_on(Platform::XoChip, &program);
+        run(&mut vm, 1);
+        assert_eq!(vm.i_reg(), 0xFFF0);
+        assert_eq!(vm.pc(), 0x204);
+        // the whole 64 KiB are addressable
+        run(&mut vm, 1);
+
+        let mut vm = vm_on(Platform::SuperChip, &program);
+        assert_eq!(
+            vm.try_tick(),
+            Err(VmError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0xF000,
+            })
+        );
+    }
+
+    #[test]
+    fn skips_over_long_instructions() {
+        let program = [
+            0x30, 0x00, // if v0 != 0 then
+            0xF0, 0x00, 0x12, 0x34, // i := long 0x1234
+            0xE0, 0xA1, // if v0 key then
+            0xF0, 0x00, 0x12, 0x34, // i := long 0x1234
+            0x61, 0x01, // v1 := 1
+        ];
+        let mut vm = vm_on(Platform::XoChip, &program);
+        run(&mut vm, 1);
+        assert_eq!(vm.pc(), 0x206);
+        run(&mut vm, 1);
+        assert_eq!(vm.pc(), 0x20C);
+        run(&mut vm, 1);
+        assert_eq!(vm.i_reg(), 0);
+        assert_eq!(vm.v_reg(1), 1);
+    }
+
+    #[test]
+    fn reports_frames() {
+        let program = [
+            0xA2, 0x0C, // i := sprite
+            0xD0, 0x11, // sprite v0 v1 1
+            0x60, 0x02, // v0 := 2
+            0xF0, 0x18, // buzzer := v0
+            0xF1, 0x0A, // v1 := key
+            0x00, 0xEE, // return
+            0x80, // sprite
+        ];
+        let mut vm = vm(&program);
+        assert_eq!(
+            vm.run_frame(3),
+            FrameReport {
+                instructions: 3,
+                display_changed: true,
+                sound_active: false,
+                waiting_for_key: false,
+                error: None,
+            }
+        );
+        // the display was not acknowledged, but this frame left it alone
+        assert!(vm.is_display_dirty());
+        assert_eq!(
+            vm.run_frame(3),
+            FrameReport {
+                instructions: 2,
+                display_changed: false,
+                sound_active: true,
+                waiting_for_key: true,
+                error: None,
+            }
+        );
+
+        vm.keypress(4, true);
+        vm.keypress(4, false);
+        let report = vm.run_frame(3);
+        assert_eq!(report.instructions, 0);
+        assert!(!report.display_changed);
+        assert!(!report.waiting_for_key);
+        assert_eq!(
+            report.error,
+            Some(VmError::StackUnderflow {
+                pc: 0x20A,
+                opcode: 0x00EE,
+            })
+        );
     }
 }
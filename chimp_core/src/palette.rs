@@ -0,0 +1,70 @@
+/// The colors a `Renderer` paints the display with, as RGBA8.
+///
+/// A color is picked by the bitplanes a pixel is set in, which gives the four
+/// colors of XO-CHIP; CHIP-8 and SUPER-CHIP only use the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// Background, first plane, second plane and both planes colors.
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    /// The chimp colors, dark olive and cream.
+    pub const CHIMP: Self = Self::from_rgb([0x1D_1A05, 0xFF_FBBD, 0xE6_AA68, 0x7F_B069]);
+    /// White on black, the second plane in greys.
+    pub const MONOCHROME: Self = Self::from_rgb([0x00_0000, 0xFF_FFFF, 0xAA_AAAA, 0x55_5555]);
+    /// An amber monochrome monitor.
+    pub const AMBER: Self = Self::from_rgb([0x14_0C00, 0xFF_B000, 0x9C_6B00, 0xFF_D37A]);
+    /// A green phosphor monochrome monitor.
+    pub const GREEN_PHOSPHOR: Self = Self::from_rgb([0x0A_1A0A, 0x33_FF66, 0x1E_9C3C, 0xA8_FFBF]);
+    /// The dark on pale green of an early handheld LCD.
+    pub const LCD: Self = Self::from_rgb([0x9B_BC0F, 0x0F_380F, 0x8B_AC0F, 0x30_6230]);
+
+    /// Names accepted by `from_name`, in the same order as `PRESETS`.
+    pub const PRESET_NAMES: [&'static str; 5] = ["chimp", "mono", "amber", "green", "lcd"];
+    const PRESETS: [Self; 5] = [
+        Self::CHIMP,
+        Self::MONOCHROME,
+        Self::AMBER,
+        Self::GREEN_PHOSPHOR,
+        Self::LCD,
+    ];
+
+    /// Creates an opaque palette from `0xRRGGBB` colors, in the order of `colors`.
+    #[must_use]
+    pub const fn from_rgb(rgb: [u32; 4]) -> Self {
+        let mut colors = [[0; 4]; 4];
+        let mut idx = 0;
+        while idx < rgb.len() {
+            let [_, r, g, b] = rgb[idx].to_be_bytes();
+            colors[idx] = [r, g, b, 0xFF];
+            idx += 1;
+        }
+        Self { colors }
+    }
+
+    /// Returns the preset with the given name, see `PRESET_NAMES`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::PRESET_NAMES
+            .iter()
+            .position(|preset| preset.eq_ignore_ascii_case(name))
+            .map(|idx| Self::PRESETS[idx])
+    }
+
+    /// Returns the color of a pixel, as given by `Vm::get_colors`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `color` is above 3.
+    #[must_use]
+    pub fn color(&self, color: u8) -> [u8; 4] {
+        self.colors[usize::from(color)]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::CHIMP
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Instruction, Quirks};
+
+/// The CHIP-8 variant a Vm emulates, which decides the available opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Platform {
+    /// The original CHIP-8, 64x32 display only.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1, adding the 128x64 high resolution mode, scrolling,
+    /// 16x16 sprites, the big font and the RPL user flags.
+    SuperChip,
+    /// XO-CHIP, extending SUPER-CHIP with 64 KiB of memory, a second bitplane
+    /// and programmable audio.
+    XoChip,
+}
+
+impl Platform {
+    /// Names accepted by `from_name`, in the same order as `ALL`.
+    pub const NAMES: [&'static str; 3] = ["chip8", "schip", "xochip"];
+    const ALL: [Self; 3] = [Self::Chip8, Self::SuperChip, Self::XoChip];
+
+    /// Returns the platform with the given name, see `NAMES`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .position(|platform| platform.eq_ignore_ascii_case(name))
+            .map(|idx| Self::ALL[idx])
+    }
+
+    /// The name of the platform, see `NAMES`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        let idx = Self::ALL.iter().position(|platform| *platform == self);
+        Self::NAMES[idx.unwrap_or_default()]
+    }
+
+    /// The quirks most programs written for this platform expect.
+    #[must_use]
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Self::Chip8 => Quirks::CHIMP,
+            Self::SuperChip => Quirks::SUPER_CHIP,
+            Self::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    /// Size of the addressable memory, in bytes.
+    #[must_use]
+    pub fn memory_size(self) -> usize {
+        match self {
+            Self::Chip8 | Self::SuperChip => 4 * 1024,
+            Self::XoChip => 64 * 1024,
+        }
+    }
+
+    /// Returns `true` if the SUPER-CHIP opcodes are available.
+    #[must_use]
+    pub fn has_super_chip(self) -> bool {
+        matches!(self, Self::SuperChip | Self::XoChip)
+    }
+
+    /// Returns `true` if the XO-CHIP opcodes are available.
+    #[must_use]
+    pub fn has_xo_chip(self) -> bool {
+        matches!(self, Self::XoChip)
+    }
+
+    /// Returns `true` if `instruction` is available.
+    #[must_use]
+    pub fn supports(self, instruction: Instruction) -> bool {
+        match instruction.platform() {
+            Self::Chip8 => true,
+            Self::SuperChip => self.has_super_chip(),
+            Self::XoChip => self.has_xo_chip(),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/// Selects between the historical interpretations of the ambiguous opcodes.
+///
+/// Every flag set to `true` enables the behaviour described on it; with the flag
+/// cleared the Vm falls back to the other common interpretation.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VX in place instead of storing the shifted VY into VX.
+    pub shift: bool,
+    /// FX55/FX65 leave I pointing after the last stored/loaded register.
+    pub load_store: bool,
+    /// BNNN jumps to VX + NNN (X being the highest nibble of NNN) instead of V0 + NNN.
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// DXYN clips sprites at the screen edges instead of wrapping them around.
+    pub clipping: bool,
+    /// DXYN waits for the next timer tick before the execution continues.
+    pub display_wait: bool,
+}
+
+// --- Presets ---
+impl Quirks {
+    /// The behaviour Chimp-8 always had, kept as the default.
+    pub const CHIMP: Self = Self {
+        shift: true,
+        load_store: false,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+    };
+
+    /// The original CHIP-8 interpreter of the COSMAC VIP.
+    pub const COSMAC_VIP: Self = Self {
+        shift: false,
+        load_store: true,
+        jump: false,
+        vf_reset: true,
+        clipping: true,
+        display_wait: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP48 calculators.
+    pub const SUPER_CHIP: Self = Self {
+        shift: true,
+        load_store: false,
+        jump: true,
+        vf_reset: false,
+        clipping: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo.
+    pub const XO_CHIP: Self = Self {
+        shift: false,
+        load_store: true,
+        jump: false,
+        vf_reset: false,
+        clipping: false,
+        display_wait: false,
+    };
+
+    /// Names accepted by `from_name`, in the same order as `PRESETS`.
+    pub const PRESET_NAMES: [&'static str; 4] = ["chimp", "vip", "schip", "xochip"];
+    const PRESETS: [Self; 4] = [
+        Self::CHIMP,
+        Self::COSMAC_VIP,
+        Self::SUPER_CHIP,
+        Self::XO_CHIP,
+    ];
+
+    /// Returns the preset with the given name, see `PRESET_NAMES`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::PRESET_NAMES
+            .iter()
+            .position(|preset| preset.eq_ignore_ascii_case(name))
+            .map(|idx| Self::PRESETS[idx])
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::CHIMP
+    }
+}
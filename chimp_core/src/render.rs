@@ -0,0 +1,285 @@
+use crate::{DirtyRect, Palette, Vm};
+
+/// Paints the display of a Vm into an RGBA8 buffer, for a frontend to upload
+/// as a texture or an image.
+///
+/// The buffer always has the size of the high resolution display times
+/// `scale`, low resolution pixels being twice as big, so it does not change
+/// when a program switches resolution.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    scale: usize,
+    palette: Palette,
+}
+
+// --- Constants ---
+impl Renderer {
+    /// Bytes per pixel of the buffer.
+    pub const BYTES_PER_PIXEL: usize = 4;
+}
+
+// --- Public Methods ---
+impl Renderer {
+    /// Creates a renderer painting a high resolution pixel as a `scale` by
+    /// `scale` square, in the default palette.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scale` is zero.
+    #[must_use]
+    pub fn new(scale: usize) -> Self {
+        assert!(scale > 0, "scale must not be zero");
+        Self {
+            scale,
+            palette: Palette::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    #[must_use]
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    /// Changes the colors, the buffer has to be painted again with `render`.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Width of the buffer, in pixels.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        Vm::MAX_SCREEN_WIDTH * self.scale
+    }
+
+    /// Height of the buffer, in pixels.
+    #[must_use]
+    pub fn height(&self) -> usize {
+        Vm::MAX_SCREEN_HEIGHT * self.scale
+    }
+
+    /// Bytes per row of the buffer.
+    #[must_use]
+    pub fn pitch(&self) -> usize {
+        self.width() * Self::BYTES_PER_PIXEL
+    }
+
+    /// Returns a buffer of the right size, filled with transparent black.
+    #[must_use]
+    pub fn buffer(&self) -> Vec<u8> {
+        vec![0; self.pitch() * self.height()]
+    }
+
+    /// Paints the whole display into `out`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out` is smaller than `buffer`.
+    pub fn render(&self, vm: &Vm, out: &mut [u8]) {
+        let screen = DirtyRect {
+            x: 0,
+            y: 0,
+            width: vm.screen_width(),
+            height: vm.screen_height(),
+        };
+        self.render_rect(vm, &screen, out);
+    }
+
+    /// Paints only the `Vm::dirty_rects` into `out`, which is enough when it
+    /// holds the display as it was at the last `Vm::acknowledge_display`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out` is smaller than `buffer`.
+    pub fn render_dirty(&self, vm: &Vm, out: &mut [u8]) {
+        for rect in vm.dirty_rects() {
+            self.render_rect(vm, rect, out);
+        }
+    }
+
+    /// Returns the area of the buffer a rectangle of the display covers, e.g.
+    /// to upload only the dirty parts.
+    #[must_use]
+    pub fn buffer_rect(&self, vm: &Vm, rect: &DirtyRect) -> DirtyRect {
+        let pixel_size = self.pixel_size(vm);
+        DirtyRect {
+            x: rect.x * pixel_size,
+            y: rect.y * pixel_size,
+            width: rect.width * pixel_size,
+            height: rect.height * pixel_size,
+        }
+    }
+}
+
+// --- Private Methods ---
+impl Renderer {
+    /// Size of a pixel of the current resolution, in buffer pixels.
+    fn pixel_size(&self, vm: &Vm) -> usize {
+        if vm.is_hires() {
+            self.scale
+        } else {
+            self.scale * 2
+        }
+    }
+
+    fn render_rect(&self, vm: &Vm, rect: &DirtyRect, out: &mut [u8]) {
+        let pixel_size = self.pixel_size(vm);
+        let pitch = self.pitch();
+        let area = self.buffer_rect(vm, rect);
+        let screen_width = vm.screen_width();
+        let first = vm.get_rows(0);
+        let second = vm.get_rows(1);
+
+        for y in rect.y..rect.y + rect.height {
+            let start = y * pixel_size * pitch + area.x * Self::BYTES_PER_PIXEL;
+            let end = start + area.width * Self::BYTES_PER_PIXEL;
+
+            // paint the first line of the pixels, then repeat it below
+            let line = &mut out[start..end];
+            let pixels = line.chunks_exact_mut(pixel_size * Self::BYTES_PER_PIXEL);
+            for (x, pixel) in (rect.x..).zip(pixels) {
+                let bit = screen_width - 1 - x;
+                let color =
+                    u8::from(first[y] >> bit & 1 != 0) | u8::from(second[y] >> bit & 1 != 0) << 1;
+                let rgba = self.palette.color(color);
+                for dot in pixel.chunks_exact_mut(Self::BYTES_PER_PIXEL) {
+                    dot.copy_from_slice(&rgba);
+                }
+            }
+            for dy in 1..pixel_size {
+                out.copy_within(start..end, start + dy * pitch);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Platform;
+    use std::convert::TryInto;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 0xFF];
+    const WHITE: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+
+    /// Sets the pixel at `x`, `y` of the current resolution.
+    fn set_pixel(vm: &mut Vm, x: u8, y: u8) {
+        vm.write_memory(0x300, &[0x80]);
+        vm.set_i_reg(0x300);
+        vm.set_v_reg(0, x);
+        vm.set_v_reg(1, y);
+        // sprite v0 v1 1
+        vm.write_memory(0x200, &[0xD0, 0x11]);
+        vm.set_pc(0x200);
+        vm.try_tick().unwrap();
+    }
+
+    fn renderer(scale: usize) -> Renderer {
+        let mut renderer = Renderer::new(scale);
+        renderer.set_palette(Palette::MONOCHROME);
+        renderer
+    }
+
+    /// Returns the color of the buffer pixel at `x`, `y`.
+    fn dot(renderer: &Renderer, out: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let at = y * renderer.pitch() + x * Renderer::BYTES_PER_PIXEL;
+        out[at..at + Renderer::BYTES_PER_PIXEL].try_into().unwrap()
+    }
+
+    #[test]
+    fn sizes_the_buffer_by_the_scale() {
+        for scale in [1, 3] {
+            let renderer = Renderer::new(scale);
+            assert_eq!(renderer.width(), 128 * scale);
+            assert_eq!(renderer.height(), 64 * scale);
+            assert_eq!(renderer.pitch(), 128 * scale * 4);
+            assert_eq!(renderer.buffer().len(), 128 * 64 * scale * scale * 4);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "scale must not be zero")]
+    fn rejects_a_zero_scale() {
+        let _ = Renderer::new(0);
+    }
+
+    #[test]
+    fn paints_lores_pixels_twice_as_big() {
+        let mut vm = Vm::default();
+        set_pixel(&mut vm, 1, 2);
+        let renderer = renderer(2);
+        let mut out = renderer.buffer();
+        renderer.render(&vm, &mut out);
+
+        for y in 0..20 {
+            for x in 0..20 {
+                let set = (4..8).contains(&x) && (8..12).contains(&y);
+                let color = if set { WHITE } else { BLACK };
+                assert_eq!(dot(&renderer, &out, x, y), color, "{x}, {y}");
+            }
+        }
+        // the whole buffer is covered
+        assert_eq!(dot(&renderer, &out, 255, 127), BLACK);
+        assert_eq!(
+            renderer.buffer_rect(&vm, &vm.dirty_rects()[0]),
+            DirtyRect {
+                x: 0,
+                y: 0,
+                width: 256,
+                height: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn paints_hires_pixels_at_the_scale() {
+        let mut vm = Vm::with_platform(Platform::SuperChip);
+        // hires
+        vm.write_memory(0x200, &[0x00, 0xFF]);
+        vm.try_tick().unwrap();
+        set_pixel(&mut vm, 1, 2);
+        let renderer = renderer(2);
+        let mut out = renderer.buffer();
+        renderer.render(&vm, &mut out);
+
+        for y in 0..10 {
+            for x in 0..10 {
+                let set = (2..4).contains(&x) && (4..6).contains(&y);
+                let color = if set { WHITE } else { BLACK };
+                assert_eq!(dot(&renderer, &out, x, y), color, "{x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn paints_only_the_dirty_rects() {
+        let mut vm = Vm::default();
+        vm.acknowledge_display();
+        set_pixel(&mut vm, 1, 2);
+        let renderer = renderer(1);
+        let mut out = renderer.buffer();
+        renderer.render_dirty(&vm, &mut out);
+
+        let area = renderer.buffer_rect(&vm, &vm.dirty_rects()[0]);
+        assert_eq!(
+            area,
+            DirtyRect {
+                x: 2,
+                y: 4,
+                width: 16,
+                height: 2,
+            }
+        );
+        assert_eq!(dot(&renderer, &out, 2, 4), WHITE);
+        assert_eq!(dot(&renderer, &out, 3, 5), WHITE);
+        assert_eq!(dot(&renderer, &out, 17, 5), BLACK);
+        // outside of it the buffer is left alone
+        assert_eq!(dot(&renderer, &out, 1, 4), [0; 4]);
+        assert_eq!(dot(&renderer, &out, 18, 4), [0; 4]);
+        assert_eq!(dot(&renderer, &out, 2, 6), [0; 4]);
+    }
+}
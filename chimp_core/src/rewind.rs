@@ -0,0 +1,247 @@
+use crate::Vm;
+use std::collections::VecDeque;
+
+/// A bounded history of recent Vm states that can be stepped back through.
+///
+/// Only the most recent snapshot is kept whole, every older one is stored as
+/// the run-length encoded XOR difference to the snapshot that followed it.
+/// Consecutive frames differ in a handful of bytes, so the history stays small
+/// even for the 64 KiB of XO-CHIP memory.
+pub struct Rewind {
+    capacity: usize,
+    interval: usize,
+    frames_since_snapshot: usize,
+    latest: Option<Vec<u8>>,
+    older: VecDeque<Snapshot>,
+}
+
+/// A snapshot relative to the one that followed it.
+enum Snapshot {
+    /// The XOR difference, used while both states have the same size.
+    Delta(Vec<u8>),
+    /// The whole state, used when the size changed (e.g. a new platform).
+    Full(Vec<u8>),
+}
+
+// --- Public Methods ---
+impl Rewind {
+    /// Creates a history of up to `capacity` snapshots, taken every `interval` frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` or `interval` is zero.
+    #[must_use]
+    pub fn new(capacity: usize, interval: usize) -> Self {
+        assert!(capacity > 0, "rewind capacity must not be zero");
+        assert!(interval > 0, "rewind interval must not be zero");
+        Self {
+            capacity,
+            interval,
+            frames_since_snapshot: 0,
+            latest: None,
+            older: VecDeque::new(),
+        }
+    }
+
+    /// Number of frames between two snapshots.
+    #[must_use]
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// Number of snapshots currently stored.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.older.len() + usize::from(self.latest.is_some())
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Forgets every snapshot, e.g. after loading another program.
+    pub fn clear(&mut self) {
+        self.frames_since_snapshot = 0;
+        self.latest = None;
+        self.older.clear();
+    }
+
+    /// Records the end of a frame, taking a snapshot of `vm` every `interval` frames.
+    pub fn record(&mut self, vm: &Vm) {
+        if self.latest.is_some() {
+            self.frames_since_snapshot += 1;
+            if self.frames_since_snapshot < self.interval {
+                return;
+            }
+        }
+        self.frames_since_snapshot = 0;
+
+        let state = vm.save_state();
+        if let Some(previous) = self.latest.replace(state) {
+            let latest = self.latest.as_deref().unwrap_or_default();
+            let snapshot = if previous.len() == latest.len() {
+                Snapshot::Delta(encode_delta(&previous, latest))
+            } else {
+                Snapshot::Full(previous)
+            };
+            self.older.push_back(snapshot);
+            while self.len() > self.capacity {
+                self.older.pop_front();
+            }
+        }
+    }
+
+    /// Restores `vm` to the snapshot taken about `frames` frames ago, or to the
+    /// oldest one if the history is shorter. The snapshots after it are dropped.
+    ///
+    /// The pressed keys are left as they are, they belong to the player and not
+    /// to the past. Returns how many frames were actually rewound.
+    pub fn rewind(&mut self, vm: &mut Vm, frames: usize) -> usize {
+        let Some(mut state) = self.latest.take() else {
+            return 0;
+        };
+
+        let mut rewound = self.frames_since_snapshot;
+        while rewound < frames {
+            match self.older.pop_back() {
+                Some(Snapshot::Delta(delta)) => apply_delta(&mut state, &delta),
+                Some(Snapshot::Full(previous)) => state = previous,
+                None => break,
+            }
+            rewound += self.interval;
+        }
+
+        let keys = vm.keys;
+        // the states were produced by `save_state`, so they always load
+        let _ = vm.load_state(&state);
+        vm.keys = keys;
+
+        self.latest = Some(state);
+        self.frames_since_snapshot = 0;
+        rewound
+    }
+}
+
+/// Encodes the XOR of two same-sized states as a sequence of
+/// `(zero run, literal length, literal bytes)` records, lengths being LEB128.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut bytes = old.iter().zip(new).map(|(old, new)| old ^ new).peekable();
+    while bytes.peek().is_some() {
+        let mut zeros = 0;
+        while bytes.next_if_eq(&0).is_some() {
+            zeros += 1;
+        }
+        let mut literal = Vec::new();
+        while let Some(byte) = bytes.next_if(|byte| *byte != 0) {
+            literal.push(byte);
+        }
+        write_len(&mut delta, zeros);
+        write_len(&mut delta, literal.len());
+        delta.extend_from_slice(&literal);
+    }
+    delta
+}
+
+/// Turns `state` back into the older state `encode_delta` compared it with.
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut delta = delta.iter().copied();
+    let mut pos = 0;
+    while let Some(zeros) = read_len(&mut delta) {
+        pos += zeros;
+        let literal_len = read_len(&mut delta).unwrap_or_default();
+        for (byte, diff) in state[pos..pos + literal_len].iter_mut().zip(&mut delta) {
+            *byte ^= diff;
+        }
+        pos += literal_len;
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        out.push((len as u8) | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+fn read_len(input: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input.next()?;
+        len |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(len);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Platform, SplitMix64};
+
+    #[test]
+    fn delta_round_trips() {
+        let old: Vec<u8> = (0..1000_u16).map(|n| n.to_le_bytes()[0]).collect();
+        let mut new = old.clone();
+        new[0] ^= 0xFF;
+        new[300..450].iter_mut().for_each(|byte| *byte ^= 0x5A);
+        new[999] = !new[999];
+
+        let delta = encode_delta(&old, &new);
+        assert!(delta.len() < 200);
+        let mut state = new.clone();
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, old);
+
+        let unchanged = encode_delta(&old, &old);
+        assert_eq!(unchanged, [0xE8, 0x07, 0x00]);
+        let mut state = old.clone();
+        apply_delta(&mut state, &unchanged);
+        assert_eq!(state, old);
+    }
+
+    #[test]
+    fn rewinds_to_earlier_frames() {
+        let mut vm = Vm::default();
+        vm.set_random_source(SplitMix64::new(1));
+        // v0 += 1, jump back
+        vm.load_program(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(4, 2);
+        let mut states = Vec::new();
+        for _ in 0..11 {
+            vm.tick();
+            vm.tick();
+            rewind.record(&vm);
+            states.push(vm.snapshot());
+        }
+        // snapshots of every other frame, only the last 4 kept
+        assert_eq!(rewind.len(), 4);
+
+        assert_eq!(rewind.rewind(&mut vm, 4), 4);
+        assert_eq!(vm.snapshot(), states[6]);
+        assert_eq!(rewind.rewind(&mut vm, 100), 2);
+        assert_eq!(vm.snapshot(), states[4]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn keeps_whole_states_across_size_changes() {
+        let mut vm = Vm::with_platform(Platform::Chip8);
+        let mut rewind = Rewind::new(10, 1);
+        rewind.record(&vm);
+        let chip8 = vm.snapshot();
+
+        vm.set_platform(Platform::XoChip);
+        rewind.record(&vm);
+        assert!(matches!(rewind.older.back(), Some(Snapshot::Full(_))));
+
+        assert_eq!(rewind.rewind(&mut vm, 1), 1);
+        assert_eq!(vm.snapshot(), chip8);
+    }
+}
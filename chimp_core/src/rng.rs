@@ -0,0 +1,53 @@
+/// A source of random bytes for CXNN.
+///
+/// The state is exposed as a single `u64` so it can be saved and restored
+/// along with the rest of the Vm, and it is `Send` so the Vm can be moved to
+/// another thread.
+pub trait RandomSource: Send {
+    /// Returns the next random byte.
+    fn next_byte(&mut self) -> u8;
+
+    /// Returns the current state of the generator.
+    fn state(&self) -> u64;
+
+    /// Restores a state previously returned by `state`.
+    fn set_state(&mut self, state: u64);
+}
+
+/// The default random source, a `SplitMix64` generator.
+///
+/// Small, fast and fully described by its 64 bit state, which makes runs
+/// reproducible from a seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    fn next_byte(&mut self) -> u8 {
+        self.next_u64().to_be_bytes()[0]
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
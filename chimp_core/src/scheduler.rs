@@ -0,0 +1,182 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Paces a Vm by wall time rather than by the frames a frontend presents.
+///
+/// The elapsed time is split into 60 Hz frames, each one running its share of
+/// the instructions per second before the timers tick. Frames are counted
+/// exactly, the instructions that do not divide evenly into frames are carried
+/// over to the next ones.
+///
+/// A frontend calls `advance` with the time since its last call, then runs a
+/// frame for every `next_frame` that returns.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    instructions_per_second: u32,
+    max_catch_up: Duration,
+    /// Wall time not run yet, in sixtieths of a nanosecond so that a frame
+    /// lasts exactly `FRAME` of them.
+    pending: u128,
+    /// Instructions owed to the coming frames, in sixtieths of an instruction,
+    /// wide enough for any rate plus the remainder carried over.
+    owed_instructions: u64,
+}
+
+// --- Constants ---
+impl Scheduler {
+    /// Rate of the delay and sound timers, in Hz.
+    pub const TIMER_RATE: u32 = 60;
+    /// Default for `max_catch_up`, 6 frames.
+    pub const DEFAULT_MAX_CATCH_UP: Duration = Duration::from_millis(100);
+    const FRAME: u128 = 1_000_000_000;
+}
+
+// --- Public Methods ---
+impl Scheduler {
+    #[must_use]
+    pub fn new(instructions_per_second: u32) -> Self {
+        Self {
+            instructions_per_second,
+            max_catch_up: Self::DEFAULT_MAX_CATCH_UP,
+            pending: 0,
+            owed_instructions: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    /// Changes the emulation speed, taking effect from the next frame.
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+    }
+
+    /// Longest time the scheduler runs to catch up at once.
+    #[must_use]
+    pub fn max_catch_up(&self) -> Duration {
+        self.max_catch_up
+    }
+
+    /// Changes how far behind the scheduler may fall before it drops time,
+    /// e.g. after the window was dragged or the program paused in a debugger.
+    /// It is never less than a frame.
+    pub fn set_max_catch_up(&mut self, max_catch_up: Duration) {
+        self.max_catch_up = max_catch_up;
+        self.pending = self.pending.min(self.max_pending());
+    }
+
+    /// Adds `elapsed` wall time to run, dropping whatever goes past `max_catch_up`.
+    pub fn advance(&mut self, elapsed: Duration) {
+        let elapsed = elapsed.as_nanos() * u128::from(Self::TIMER_RATE);
+        self.pending = self.pending.saturating_add(elapsed).min(self.max_pending());
+    }
+
+    /// Starts the next frame if its time has come, returning how many
+    /// instructions to run before ticking the timers.
+    pub fn next_frame(&mut self) -> Option<u32> {
+        if self.pending < Self::FRAME {
+            return None;
+        }
+        self.pending -= Self::FRAME;
+
+        let rate = u64::from(Self::TIMER_RATE);
+        self.owed_instructions += u64::from(self.instructions_per_second);
+        let instructions = self.owed_instructions / rate;
+        self.owed_instructions %= rate;
+        Some(u32::try_from(instructions).unwrap_or(u32::MAX))
+    }
+
+    /// Returns how long until `next_frame` starts another frame, zero if one
+    /// is due, for a frontend to sleep that long rather than wait on vsync.
+    #[must_use]
+    pub fn time_until_next_frame(&self) -> Duration {
+        let remaining = Self::FRAME.saturating_sub(self.pending);
+        let nanos = remaining.div_ceil(u128::from(Self::TIMER_RATE));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Forgets the time not run yet, e.g. when resuming after a pause.
+    pub fn clear(&mut self) {
+        self.pending = 0;
+        self.owed_instructions = 0;
+    }
+}
+
+// --- Private Methods ---
+impl Scheduler {
+    fn max_pending(&self) -> u128 {
+        let max = self.max_catch_up.as_nanos() * u128::from(Self::TIMER_RATE);
+        max.max(Self::FRAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_nanos(16_666_667);
+
+    fn frames(scheduler: &mut Scheduler) -> Vec<u32> {
+        std::iter::from_fn(|| scheduler.next_frame()).collect()
+    }
+
+    #[test]
+    fn runs_frames_at_the_timer_rate() {
+        let mut scheduler = Scheduler::new(700);
+        let mut total = Vec::new();
+        for _ in 0..1000 {
+            scheduler.advance(Duration::from_millis(1));
+            total.extend(frames(&mut scheduler));
+        }
+        assert_eq!(total.len(), 60);
+        assert_eq!(total.iter().sum::<u32>(), 700);
+        assert!(total.iter().all(|&count| count == 11 || count == 12));
+    }
+
+    #[test]
+    fn caps_catch_up() {
+        let mut scheduler = Scheduler::new(600);
+        scheduler.advance(Duration::from_secs(10));
+        assert_eq!(frames(&mut scheduler), [10; 6]);
+
+        scheduler.set_max_catch_up(Duration::from_millis(50));
+        scheduler.advance(Duration::from_secs(10));
+        assert_eq!(frames(&mut scheduler).len(), 3);
+
+        scheduler.set_max_catch_up(Duration::ZERO);
+        scheduler.advance(Duration::from_secs(10));
+        assert_eq!(frames(&mut scheduler).len(), 1);
+
+        scheduler.advance(FRAME);
+        scheduler.clear();
+        assert_eq!(scheduler.next_frame(), None);
+    }
+
+    #[test]
+    fn runs_any_rate() {
+        let mut scheduler = Scheduler::new(u32::MAX);
+        scheduler.advance(FRAME * 3);
+        let frames = frames(&mut scheduler);
+        assert_eq!(frames.len(), 3);
+        let total: u64 = frames.iter().map(|&count| u64::from(count)).sum();
+        assert_eq!(total, u64::from(u32::MAX) / 20);
+    }
+
+    #[test]
+    fn time_until_next_frame() {
+        let mut scheduler = Scheduler::new(600);
+        assert_eq!(scheduler.time_until_next_frame(), FRAME);
+        scheduler.advance(Duration::from_millis(10));
+        assert_eq!(
+            scheduler.time_until_next_frame(),
+            Duration::from_nanos(6_666_667)
+        );
+        scheduler.advance(Duration::from_millis(10));
+        assert_eq!(scheduler.time_until_next_frame(), Duration::ZERO);
+        assert_eq!(scheduler.next_frame(), Some(10));
+        assert!(scheduler.time_until_next_frame() < FRAME);
+        assert_eq!(scheduler.next_frame(), None);
+    }
+}
@@ -0,0 +1,669 @@
+//! Save states, a versioned binary snapshot of everything inside a `Vm`.
+//!
+//! # Format
+//!
+//! All numbers are little-endian.
+//!
+//! | Offset    | Size | Content                                    |
+//! |-----------|------|--------------------------------------------|
+//! | 0         | 4    | magic, the ASCII bytes `C8ST`              |
+//! | 4         | 2    | format version, currently `1`              |
+//! | 6         | 4    | payload length `N`                         |
+//! | 10        | N    | payload                                    |
+//! | 10 + N    | 4    | CRC-32 (IEEE) of the payload               |
+//!
+//! The payload is, in order:
+//!
+//! | Size        | Content                                                         |
+//! |-------------|-----------------------------------------------------------------|
+//! | 1           | platform: `0` CHIP-8, `1` SUPER-CHIP, `2` XO-CHIP               |
+//! | 1           | quirks, bit 0 to 5: shift, load/store, jump, vf reset, clipping, display wait |
+//! | 2           | PC                                                              |
+//! | 2           | I                                                               |
+//! | 16          | V0 to VF                                                        |
+//! | 1           | SP                                                              |
+//! | 32          | stack, 16 addresses                                             |
+//! | 1           | delay timer                                                     |
+//! | 1           | sound timer                                                     |
+//! | 2           | keys, bit N set while key N is pressed                          |
+//! | 16          | RPL user flags                                                  |
+//! | 1           | flags, bit 0: hires, bit 1: exited, bit 2: waiting for the display, bit 3: waiting for a key |
+//! | 1           | selected bitplanes                                              |
+//! | 1           | pitch                                                           |
+//! | 16          | audio pattern                                                   |
+//! | 1           | error kind: `0` none, then the `VmError` variants in declaration order |
+//! | 2           | error PC                                                        |
+//! | 2           | error opcode                                                    |
+//! | 4           | error address (`MemoryOutOfBounds`) or key (`InvalidKey`)       |
+//! | 8           | random source state                                             |
+//! | 1           | register FX0A stores the key in, `0` unless flag bit 3 is set   |
+//! | 1           | key pressed since FX0A started waiting, `0xFF` if none          |
+//! | 2           | load address, see `Vm::set_load_address`                        |
+//! | 4           | memory size `M`                                                 |
+//! | M           | memory                                                          |
+//! | 2 x 1024    | bitplanes, 128x64 pixels each, row by row, 8 pixels per byte with the leftmost in the highest bit |
+//!
+//! The display is always stored at the high resolution size, the low resolution
+//! pixels occupying the first 64x32 entries laid out like `Vm::get_display`.
+//!
+//! Newer versions of the crate keep loading the older format versions.
+
+use crate::{KeyWait, Platform, Quirks, Vm, VmError};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An error raised when a save state cannot be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic.
+    BadMagic,
+    /// The data was written by a newer, unknown format version.
+    UnsupportedVersion(u16),
+    /// The data ends before the save state does.
+    Truncated,
+    /// The payload does not match its checksum.
+    ChecksumMismatch,
+    /// The payload holds a value the Vm cannot be in.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            Self::Truncated => write!(f, "save state is truncated"),
+            Self::ChecksumMismatch => write!(f, "save state is corrupted"),
+            Self::Invalid(what) => write!(f, "save state holds an invalid {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+type Result<T> = std::result::Result<T, StateError>;
+
+const MAGIC: [u8; 4] = *b"C8ST";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 10;
+const CHECKSUM_SIZE: usize = 4;
+const PACKED_PLANE_SIZE: usize = Vm::DISPLAY_SIZE / 8;
+
+const FLAG_HIRES: u8 = 1 << 0;
+const FLAG_EXITED: u8 = 1 << 1;
+const FLAG_VBLANK_WAIT: u8 = 1 << 2;
+const FLAG_KEY_WAIT: u8 = 1 << 3;
+const NO_KEY: u8 = 0xFF;
+
+// --- Public Methods ---
+impl Vm {
+    /// Captures the whole state of the Vm, see the `state` module for the format.
+    #[must_use]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Writer::default();
+        self.write_payload(&mut payload);
+        let payload = payload.0;
+
+        let mut data = Writer(Vec::with_capacity(
+            HEADER_SIZE + payload.len() + CHECKSUM_SIZE,
+        ));
+        data.bytes(&MAGIC);
+        data.u16(VERSION);
+        data.len(payload.len());
+        data.bytes(&payload);
+        data.u32(crc32(&payload));
+        data.0
+    }
+
+    /// Restores a state captured by `save_state`, including platform and quirks.
+    ///
+    /// The Vm is left untouched if the state cannot be loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StateError` if `data` is not a save state, comes from an unknown
+    /// format version, is truncated or corrupted.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut header = Reader(data);
+        if header.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = header.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let len = header.u32()? as usize;
+        let payload = header.bytes(len)?;
+        if header.u32()? != crc32(payload) {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        let state = State::read(&mut Reader(payload))?;
+        state.apply(self);
+        Ok(())
+    }
+}
+
+// --- Private Methods ---
+impl Vm {
+    fn write_payload(&self, out: &mut Writer) {
+        out.u8(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        out.u8(quirks_to_bits(self.quirks));
+        out.u16(self.pc);
+        out.u16(self.i_reg);
+        out.bytes(&self.v_reg);
+        out.u8(u8::try_from(self.sp).unwrap_or(u8::MAX));
+        for addr in self.stack {
+            out.u16(addr);
+        }
+        out.u8(self.delay_timer);
+        out.u8(self.sound_timer);
+        out.u16(pack_bits(&self.keys));
+        out.bytes(&self.rpl);
+
+        let mut flags = 0;
+        if self.hires {
+            flags |= FLAG_HIRES;
+        }
+        if self.exited {
+            flags |= FLAG_EXITED;
+        }
+        if self.vblank_wait {
+            flags |= FLAG_VBLANK_WAIT;
+        }
+        if self.key_wait.is_some() {
+            flags |= FLAG_KEY_WAIT;
+        }
+        out.u8(flags);
+        out.u8(self.planes);
+        out.u8(self.pitch);
+        out.bytes(&self.audio_pattern);
+
+        write_error(out, self.error);
+        out.u64(self.rng.state());
+        let wait = self
+            .key_wait
+            .map_or([0, 0], |wait| [wait.reg, wait.key.unwrap_or(NO_KEY)]);
+        out.bytes(&wait);
+        out.u16(self.load_addr);
+
+        out.len(self.memory.len());
+        out.bytes(&self.memory);
+        let row_size = self.screen_width() / 8;
+        for plane in 0..Vm::PLANE_COUNT {
+            let mut packed = Vec::with_capacity(PACKED_PLANE_SIZE);
+            for row in self.get_rows(plane) {
+                packed.extend_from_slice(&row.to_be_bytes()[16 - row_size..]);
+            }
+            packed.resize(PACKED_PLANE_SIZE, 0);
+            out.bytes(&packed);
+        }
+    }
+}
+
+/// A fully parsed and validated payload, applied to the Vm only once complete.
+struct State {
+    platform: Platform,
+    quirks: Quirks,
+    pc: u16,
+    i_reg: u16,
+    v_reg: [u8; Vm::REG_COUNT],
+    sp: u16,
+    stack: [u16; Vm::STACK_SIZE],
+    delay_timer: u8,
+    sound_timer: u8,
+    keys: [bool; Vm::KEYS_COUNT],
+    rpl: [u8; Vm::RPL_COUNT],
+    flags: u8,
+    planes: u8,
+    pitch: u8,
+    audio_pattern: [u8; Vm::AUDIO_PATTERN_SIZE],
+    error: Option<VmError>,
+    rng: u64,
+    key_wait: Option<KeyWait>,
+    load_addr: u16,
+    memory: Vec<u8>,
+    display: [[u128; Vm::MAX_SCREEN_HEIGHT]; Vm::PLANE_COUNT],
+}
+
+impl State {
+    fn read(input: &mut Reader) -> Result<Self> {
+        let platform = match input.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            _ => return Err(StateError::Invalid("platform")),
+        };
+        let quirks = quirks_from_bits(input.u8()?);
+        let pc = input.u16()?;
+        let i_reg = input.u16()?;
+        let v_reg = input.array()?;
+        let sp = u16::from(input.u8()?);
+        if usize::from(sp) > Vm::STACK_SIZE {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let mut stack = [0; Vm::STACK_SIZE];
+        for addr in &mut stack {
+            *addr = input.u16()?;
+        }
+        let delay_timer = input.u8()?;
+        let sound_timer = input.u8()?;
+        let mut keys = [false; Vm::KEYS_COUNT];
+        unpack_bits(input.u16()?, &mut keys);
+        let rpl = input.array()?;
+        let flags = input.u8()?;
+        let planes = input.u8()?;
+        if planes > 0b11 {
+            return Err(StateError::Invalid("bitplanes"));
+        }
+        let pitch = input.u8()?;
+        let audio_pattern = input.array()?;
+        let error = read_error(input)?;
+        let rng = input.u64()?;
+        let key_wait = read_key_wait(input, flags)?;
+        let load_addr = input.u16()?;
+
+        let memory_size = input.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        let memory = input.bytes(memory_size)?.to_vec();
+
+        let row_size = if flags & FLAG_HIRES != 0 { 16 } else { 8 };
+        let mut display = [[0; Vm::MAX_SCREEN_HEIGHT]; Vm::PLANE_COUNT];
+        for plane in &mut display {
+            let packed = input.bytes(PACKED_PLANE_SIZE)?;
+            for (row, bytes) in plane.iter_mut().zip(packed.chunks_exact(row_size)) {
+                let mut be_bytes = [0; 16];
+                be_bytes[16 - row_size..].copy_from_slice(bytes);
+                *row = u128::from_be_bytes(be_bytes);
+            }
+        }
+
+        Ok(Self {
+            platform,
+            quirks,
+            pc,
+            i_reg,
+            v_reg,
+            sp,
+            stack,
+            delay_timer,
+            sound_timer,
+            keys,
+            rpl,
+            flags,
+            planes,
+            pitch,
+            audio_pattern,
+            error,
+            rng,
+            key_wait,
+            load_addr,
+            memory,
+            display,
+        })
+    }
+
+    fn apply(self, vm: &mut Vm) {
+        vm.platform = self.platform;
+        vm.quirks = self.quirks;
+        vm.pc = self.pc;
+        vm.i_reg = self.i_reg;
+        vm.v_reg = self.v_reg;
+        vm.sp = self.sp;
+        vm.stack = self.stack;
+        vm.delay_timer = self.delay_timer;
+        vm.sound_timer = self.sound_timer;
+        vm.keys = self.keys;
+        vm.rpl = self.rpl;
+        vm.hires = self.flags & FLAG_HIRES != 0;
+        vm.exited = self.flags & FLAG_EXITED != 0;
+        vm.vblank_wait = self.flags & FLAG_VBLANK_WAIT != 0;
+        vm.planes = self.planes;
+        vm.pitch = self.pitch;
+        vm.audio_pattern = self.audio_pattern;
+        vm.error = self.error;
+        vm.key_wait = self.key_wait;
+        vm.breakpoint = None;
+        vm.load_addr = self.load_addr;
+        vm.rng.set_state(self.rng);
+        vm.memory = self.memory;
+        vm.display = self.display;
+        vm.mark_display_dirty();
+        vm.clear_decode_cache();
+    }
+}
+
+fn quirks_to_bits(quirks: Quirks) -> u8 {
+    pack_bits(&[
+        quirks.shift,
+        quirks.load_store,
+        quirks.jump,
+        quirks.vf_reset,
+        quirks.clipping,
+        quirks.display_wait,
+    ])
+    .to_le_bytes()[0]
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let mut flags = [false; 6];
+    unpack_bits(u16::from(bits), &mut flags);
+    let [shift, load_store, jump, vf_reset, clipping, display_wait] = flags;
+    Quirks {
+        shift,
+        load_store,
+        jump,
+        vf_reset,
+        clipping,
+        display_wait,
+    }
+}
+
+fn write_error(out: &mut Writer, error: Option<VmError>) {
+    let (kind, extra) = match error {
+        None => (0, 0),
+        Some(VmError::UnknownOpcode { .. }) => (1, 0),
+        Some(VmError::StackOverflow { .. }) => (2, 0),
+        Some(VmError::StackUnderflow { .. }) => (3, 0),
+        Some(VmError::MemoryOutOfBounds { addr, .. }) => (4, addr),
+        Some(VmError::InvalidKey { key, .. }) => (5, usize::from(key)),
+    };
+    out.u8(kind);
+    out.u16(error.map_or(0, |e| e.pc()));
+    out.u16(error.map_or(0, |e| e.opcode()));
+    out.len(extra);
+}
+
+fn read_error(input: &mut Reader) -> Result<Option<VmError>> {
+    let kind = input.u8()?;
+    let pc = input.u16()?;
+    let opcode = input.u16()?;
+    let extra = input.u32()?;
+    let error = match kind {
+        0 => None,
+        1 => Some(VmError::UnknownOpcode { pc, opcode }),
+        2 => Some(VmError::StackOverflow { pc, opcode }),
+        3 => Some(VmError::StackUnderflow { pc, opcode }),
+        4 => Some(VmError::MemoryOutOfBounds {
+            pc,
+            opcode,
+            addr: extra as usize,
+        }),
+        5 => Some(VmError::InvalidKey {
+            pc,
+            opcode,
+            key: u8::try_from(extra).map_err(|_| StateError::Invalid("error"))?,
+        }),
+        _ => return Err(StateError::Invalid("error")),
+    };
+    Ok(error)
+}
+
+fn read_key_wait(input: &mut Reader, flags: u8) -> Result<Option<KeyWait>> {
+    let [reg, key] = input.array()?;
+    if flags & FLAG_KEY_WAIT == 0 {
+        return Ok(None);
+    }
+    let key = (key != NO_KEY).then_some(key);
+    if usize::from(reg) >= Vm::REG_COUNT
+        || key.is_some_and(|key| usize::from(key) >= Vm::KEYS_COUNT)
+    {
+        return Err(StateError::Invalid("key wait"));
+    }
+    Ok(Some(KeyWait { reg, key }))
+}
+
+/// Packs up to 16 flags, the first one ending in the lowest bit.
+fn pack_bits(flags: &[bool]) -> u16 {
+    flags
+        .iter()
+        .rev()
+        .fold(0, |bits, flag| (bits << 1) | u16::from(*flag))
+}
+
+/// Unpacks the flags packed by `pack_bits`.
+fn unpack_bits(bits: u16, flags: &mut [bool]) {
+    for (idx, flag) in flags.iter_mut().enumerate() {
+        *flag = bits & (1 << idx) != 0;
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Writes a length as a `u32`, lengths inside a Vm never come close to its limit.
+    #[allow(clippy::cast_possible_truncation)]
+    fn len(&mut self, len: usize) {
+        self.u32(len as u32);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ 0xEDB8_8320
+            };
+            bit += 1;
+        }
+        table[idx as usize] = crc;
+        idx += 1;
+    }
+    table
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use crate::Vm;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Serializes the Vm as its save state bytes.
+    impl Serialize for Vm {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.save_state())
+        }
+    }
+
+    /// Deserializes a Vm from its save state bytes.
+    impl<'de> Deserialize<'de> for Vm {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let data = <Vec<u8>>::deserialize(deserializer)?;
+            let mut vm = Vm::default();
+            vm.load_state(&data).map_err(de::Error::custom)?;
+            Ok(vm)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offset of the selected bitplanes in a save state.
+    const PLANES_OFFSET: usize = HEADER_SIZE + 76;
+
+    /// Changes a byte of the payload and fixes the checksum, as a corruption
+    /// the CRC cannot catch would.
+    fn corrupt(data: &mut [u8], offset: usize, value: u8) {
+        data[offset] = value;
+        let end = data.len() - CHECKSUM_SIZE;
+        let crc = crc32(&data[HEADER_SIZE..end]);
+        data[end..].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// An XO-CHIP Vm that drew, scrolled and changed most of its registers.
+    fn busy_vm() -> Vm {
+        let mut vm = Vm::with_platform(Platform::XoChip);
+        vm.set_random_source(crate::SplitMix64::new(7));
+        let program = [
+            0x00, 0xFF, // hires
+            0xF3, 0x01, // plane 3
+            0x6A, 0x20, // va := 0x20
+            0xFA, 0x29, // i := hex va
+            0xDA, 0xA5, // sprite va va 5
+            0x00, 0xC3, // scroll-down 3
+            0xCB, 0xFF, // vb := random 0xFF
+            0x2F, 0x00, // call 0xF00
+            0xF5, 0x15, // delay := v5
+        ];
+        vm.load_program(&program).unwrap();
+        vm.keypress(0x4, true);
+        for _ in 0..8 {
+            vm.tick();
+        }
+        assert_eq!(vm.sp(), 1);
+        assert!(!vm.is_halted());
+        vm
+    }
+
+    #[test]
+    fn round_trips() {
+        let vm = busy_vm();
+        let data = vm.save_state();
+
+        let mut loaded = Vm::default();
+        loaded.load_state(&data).unwrap();
+        assert_eq!(loaded.snapshot(), vm.snapshot());
+        assert_eq!(loaded.get_rows(0), vm.get_rows(0));
+        assert_eq!(loaded.get_rows(1), vm.get_rows(1));
+        assert_eq!(loaded.save_state(), data);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut vm = busy_vm();
+        let data = vm.save_state();
+        let before = vm.snapshot();
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(vm.load_state(&bad_magic), Err(StateError::BadMagic));
+
+        for version in [0, VERSION + 1] {
+            let mut bad_version = data.clone();
+            bad_version[4..6].copy_from_slice(&version.to_le_bytes());
+            assert_eq!(
+                vm.load_state(&bad_version),
+                Err(StateError::UnsupportedVersion(version))
+            );
+        }
+
+        let mut bad_crc = data.clone();
+        bad_crc[PLANES_OFFSET] ^= 0b10;
+        assert_eq!(vm.load_state(&bad_crc), Err(StateError::ChecksumMismatch));
+
+        assert_eq!(
+            vm.load_state(&data[..data.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(vm.load_state(&[]), Err(StateError::Truncated));
+
+        assert_eq!(vm.snapshot(), before);
+    }
+
+    #[test]
+    fn rejects_invalid_bitplanes() {
+        let mut vm = Vm::with_platform(Platform::XoChip);
+        let mut data = vm.save_state();
+        assert_eq!(data[PLANES_OFFSET], 1);
+
+        corrupt(&mut data, PLANES_OFFSET, 0b100);
+        assert_eq!(vm.load_state(&data), Err(StateError::Invalid("bitplanes")));
+
+        corrupt(&mut data, PLANES_OFFSET, 0b11);
+        assert_eq!(vm.load_state(&data), Ok(()));
+        assert_eq!(vm.planes, 0b11);
+    }
+
+    #[test]
+    fn restores_load_address() {
+        let mut eti = Vm::default();
+        eti.set_load_address(Vm::ETI_660_START_ADDR);
+        eti.reset();
+        let data = eti.save_state();
+
+        let mut vm = Vm::default();
+        vm.load_state(&data).unwrap();
+        assert_eq!(vm.load_address(), Vm::ETI_660_START_ADDR);
+        vm.reset();
+        assert_eq!(vm.pc(), Vm::ETI_660_START_ADDR);
+    }
+}
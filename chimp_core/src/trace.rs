@@ -0,0 +1,323 @@
+//! Execution traces, one line per executed instruction.
+//!
+//! # Format
+//!
+//! Each line describes the state right before the instruction runs, as
+//! space-separated `NAME=VALUE` fields after the cycle, then the disassembly:
+//!
+//! ```text
+//! 42 PC=0204 OP=6A02 V0=00 V1=1F V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00 VA=00 VB=00 VC=00 VD=00 VE=00 VF=01 I=02A8 SP=01 DT=00 ST=00 MEM=1B2E4F0A ; LD VA, 0x02
+//! ```
+//!
+//! | Field     | Content                                                       |
+//! |-----------|---------------------------------------------------------------|
+//! | cycle     | decimal number of instructions traced before this one         |
+//! | `PC`      | address of the instruction, 4 hex digits                      |
+//! | `OP`      | the instruction's bytes in hex, 8 digits for F000 NNNN        |
+//! | `V0`-`VF` | registers, 2 hex digits each                                  |
+//! | `I`       | 4 hex digits                                                  |
+//! | `SP`      | stack pointer, 2 hex digits                                   |
+//! | `DT`/`ST` | delay and sound timers, 2 hex digits each                     |
+//! | `MEM`     | CRC-32 (IEEE) of the whole memory, 8 hex digits               |
+//! | `; ...`   | the instruction in `disasm::Syntax::Cowgod`, up to the line end |
+//!
+//! Hex digits are uppercase. Ticks that execute nothing (a halted Vm, or one
+//! waiting for the display or for FX0A's key) produce no line.
+//!
+//! `TraceLine` parses lines back. It accepts any set of fields, so traces from
+//! other emulators only need to be converted to this layout to be compared.
+
+use crate::disasm::{self, Syntax};
+use crate::state::crc32;
+use crate::Vm;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// A line of a trace, parsed back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub cycle: u64,
+    /// The `NAME=VALUE` fields, in the order of the line.
+    pub fields: Vec<(String, String)>,
+    /// Everything after `;`, empty if the line has no disassembly.
+    pub disassembly: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseTraceError {
+    /// The line does not start with a decimal cycle.
+    InvalidCycle(String),
+    /// A field is not written `NAME=VALUE`.
+    InvalidField(String),
+}
+
+/// An active trace, see the module documentation for the format.
+pub(crate) struct Trace {
+    out: Box<dyn Write + Send>,
+    cycle: u64,
+    /// Checksum of the memory, computed again only after it was written to.
+    memory_crc: Option<u32>,
+    /// The first write error, after which nothing more is written.
+    error: Option<io::Error>,
+}
+
+// --- Public Methods ---
+impl Vm {
+    /// Writes a line to `out` for every instruction executed from now on,
+    /// replacing any trace already running.
+    pub fn start_trace(&mut self, out: impl Write + Send + 'static) {
+        self.trace = Some(Trace {
+            out: Box::new(out),
+            cycle: 0,
+            memory_crc: None,
+            error: None,
+        });
+    }
+
+    /// Stops tracing and flushes the output.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error writing the trace, which stopped it early.
+    pub fn stop_trace(&mut self) -> io::Result<()> {
+        match self.trace.take() {
+            Some(Trace {
+                error: Some(error), ..
+            }) => Err(error),
+            Some(mut trace) => trace.out.flush(),
+            None => Ok(()),
+        }
+    }
+
+    #[must_use]
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+}
+
+// --- Private Methods ---
+impl Vm {
+    /// Writes the line of the instruction at the Program Counter, if tracing.
+    pub(crate) fn trace_instruction(&mut self) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        let Some(code) = self.memory.get(self.pc as usize..) else {
+            return;
+        };
+        if trace.error.is_some() || code.is_empty() {
+            return;
+        }
+
+        let (text, len) = disasm::disassemble(code, Syntax::Cowgod);
+        let mut line = format!("{} PC={:04X} OP=", trace.cycle, self.pc);
+        for byte in &code[..len] {
+            let _ = write!(line, "{byte:02X}");
+        }
+        for (x, vx) in self.v_reg.iter().enumerate() {
+            let _ = write!(line, " V{x:X}={vx:02X}");
+        }
+        let memory = &self.memory;
+        let memory_crc = *trace.memory_crc.get_or_insert_with(|| crc32(memory));
+        let _ = writeln!(
+            line,
+            " I={:04X} SP={:02X} DT={:02X} ST={:02X} MEM={memory_crc:08X} ; {text}",
+            self.i_reg, self.sp, self.delay_timer, self.sound_timer
+        );
+
+        trace.error = trace.out.write_all(line.as_bytes()).err();
+        trace.cycle += 1;
+    }
+
+    /// Drops the checksum of the memory, after it was written to.
+    pub(crate) fn forget_memory_crc(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.memory_crc = None;
+        }
+    }
+}
+
+impl TraceLine {
+    /// Returns the value of the field `name`, e.g. `"PC"`.
+    #[must_use]
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the fields both lines have but with different values, as
+    /// `(name, self's value, other's value)`.
+    ///
+    /// The disassembly is not compared, emulators spell instructions differently.
+    #[must_use]
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Vec<(&'a str, &'a str, &'a str)> {
+        self.fields
+            .iter()
+            .filter_map(|(name, value)| match other.field(name) {
+                Some(other_value) if other_value != value => {
+                    Some((name.as_str(), value.as_str(), other_value))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl FromStr for TraceLine {
+    type Err = ParseTraceError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (state, disassembly) = line.split_once(';').unwrap_or((line, ""));
+        let mut tokens = state.split_whitespace();
+
+        let cycle = tokens.next().unwrap_or_default();
+        let cycle = cycle
+            .parse()
+            .map_err(|_| ParseTraceError::InvalidCycle(cycle.to_string()))?;
+
+        let fields = tokens
+            .map(|token| match token.split_once('=') {
+                Some((name, value)) if !name.is_empty() => {
+                    Ok((name.to_string(), value.to_string()))
+                }
+                _ => Err(ParseTraceError::InvalidField(token.to_string())),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            cycle,
+            fields,
+            disassembly: disassembly.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cycle)?;
+        for (name, value) in &self.fields {
+            write!(f, " {name}={value}")?;
+        }
+        if !self.disassembly.is_empty() {
+            write!(f, " ; {}", self.disassembly)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ParseTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCycle(token) => write!(f, "expected a cycle, found '{token}'"),
+            Self::InvalidField(token) => write!(f, "expected NAME=VALUE, found '{token}'"),
+        }
+    }
+}
+
+impl std::error::Error for ParseTraceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Platform;
+    use std::sync::{Arc, Mutex};
+
+    /// A trace output the test can still read once the Vm owns it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(platform: Platform, program: &[u8], ticks: usize) -> Vec<String> {
+        let mut vm = Vm::with_platform(platform);
+        vm.load_program(program).unwrap();
+        let out = Shared::default();
+        vm.start_trace(out.clone());
+        for _ in 0..ticks {
+            vm.tick();
+        }
+        vm.stop_trace().unwrap();
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn writes_the_documented_format() {
+        let lines = trace(
+            Platform::XoChip,
+            &[0x6A, 0x02, 0xF0, 0x00, 0x12, 0x34, 0xFA, 0x55, 0x00, 0xE0],
+            4,
+        );
+        let regs = "V0=00 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00 V8=00 V9=00";
+        assert_eq!(
+            lines,
+            [
+                format!("0 PC=0200 OP=6A02 {regs} VA=00 VB=00 VC=00 VD=00 VE=00 VF=00 I=0000 SP=00 DT=00 ST=00 MEM=BFBA65D7 ; LD VA, 0x02"),
+                format!("1 PC=0202 OP=F0001234 {regs} VA=02 VB=00 VC=00 VD=00 VE=00 VF=00 I=0000 SP=00 DT=00 ST=00 MEM=BFBA65D7 ; LD I, long 0x1234"),
+                format!("2 PC=0206 OP=FA55 {regs} VA=02 VB=00 VC=00 VD=00 VE=00 VF=00 I=1234 SP=00 DT=00 ST=00 MEM=BFBA65D7 ; LD [I], VA"),
+                format!("3 PC=0208 OP=00E0 {regs} VA=02 VB=00 VC=00 VD=00 VE=00 VF=00 I=123F SP=00 DT=00 ST=00 MEM=F4173B8C ; CLS"),
+            ]
+        );
+    }
+
+    #[test]
+    fn checksums_the_memory_after_every_write() {
+        let mut vm = Vm::default();
+        // i := 0x300, bcd v0, v0 += 7, jump 0x202
+        vm.load_program(&[0xA3, 0x00, 0xF0, 0x33, 0x70, 0x07, 0x12, 0x02])
+            .unwrap();
+        let out = Shared::default();
+        vm.start_trace(out.clone());
+        let mut expected = Vec::new();
+        for step in 0..40 {
+            if step == 20 {
+                vm.write_memory(0x400, &[1, 2, 3]);
+            }
+            expected.push(format!("MEM={:08X}", crc32(vm.memory())));
+            vm.tick();
+        }
+        vm.stop_trace().unwrap();
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let found: Vec<_> = text
+            .lines()
+            .map(|line| line.parse::<TraceLine>().unwrap())
+            .map(|line| format!("MEM={}", line.field("MEM").unwrap()))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn parses_lines_back() {
+        let text = "7 PC=0204 OP=6A02 VA=1F I=02A8 ; LD VA, 0x02";
+        let line: TraceLine = text.parse().unwrap();
+        assert_eq!(line.cycle, 7);
+        assert_eq!(line.field("VA"), Some("1F"));
+        assert_eq!(line.field("VB"), None);
+        assert_eq!(line.disassembly, "LD VA, 0x02");
+        assert_eq!(line.to_string(), text);
+
+        let other: TraceLine = "7 PC=0204 VA=20 I=02A8 MEM=00".parse().unwrap();
+        assert_eq!(line.diff(&other), [("VA", "1F", "20")]);
+
+        assert_eq!(
+            "x PC=0200".parse::<TraceLine>(),
+            Err(ParseTraceError::InvalidCycle("x".to_string()))
+        );
+        assert_eq!(
+            "1 PC".parse::<TraceLine>(),
+            Err(ParseTraceError::InvalidField("PC".to_string()))
+        );
+    }
+}
@@ -1,68 +1,236 @@
 #![warn(clippy::pedantic, clippy::all)]
-use chimp_core::Vm;
+use chimp_core::{
+    Beeper, Palette, Platform, Quirks, Renderer, Rewind, Scheduler, SplitMix64, Vm, VmError,
+};
 use sdl2::{
-    event::Event, keyboard::Keycode, pixels::Color, rect::Rect, render::Canvas, video::Window,
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired},
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{Canvas, Texture},
+    video::Window,
     EventPump, Sdl,
 };
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 type Error = Box<dyn std::error::Error>;
 type Result<T> = std::result::Result<T, Error>;
 
+/// Plays the buzzer on SDL's audio thread, while the main thread says it sounds.
+struct Speaker {
+    beeper: Beeper,
+    is_active: Arc<AtomicBool>,
+}
+
+impl AudioCallback for Speaker {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.beeper
+            .fill(self.is_active.load(Ordering::Relaxed), out);
+    }
+}
+
 struct App {
     sdl_context: Sdl,
     canvas: Canvas<Window>,
+    renderer: Renderer,
+    /// The display as last rendered, uploaded to the texture `run` creates.
+    framebuffer: Vec<u8>,
+    /// Kept open for the sound to play, `None` when there is no audio device.
+    _speaker: Option<AudioDevice<Speaker>>,
+    is_sound_active: Arc<AtomicBool>,
     vm: Vm,
+    scheduler: Scheduler,
+    rewind: Rewind,
+    title: String,
     is_running: bool,
+    is_rewinding: bool,
+    /// Set when the window lost its picture, the Vm tracks its own changes
+    /// and the framebuffer keeps them.
+    needs_redraw: bool,
 }
 
 // --- Constants ---
 impl App {
-    const SCALE: u32 = 15;
+    /// Size of a high resolution pixel, low resolution pixels are twice as big.
+    const SCALE: u32 = 8;
     #[allow(clippy::cast_possible_truncation)]
-    const WINDOW_WIDTH: u32 = (Vm::SCREEN_WIDTH as u32) * Self::SCALE;
+    const WINDOW_WIDTH: u32 = (Vm::MAX_SCREEN_WIDTH as u32) * Self::SCALE;
     #[allow(clippy::cast_possible_truncation)]
-    const WINDOW_HEIGHT: u32 = (Vm::SCREEN_HEIGHT as u32) * Self::SCALE;
-    const TICKS_PER_FRAME: usize = 10;
+    const WINDOW_HEIGHT: u32 = (Vm::MAX_SCREEN_HEIGHT as u32) * Self::SCALE;
+    /// Default emulation speed, 10 instructions per 60 Hz frame.
+    const INSTRUCTIONS_PER_SECOND: u32 = 600;
+    const SAMPLE_RATE: i32 = 44_100;
+    /// Snapshot every other frame for 20 seconds of history.
+    const REWIND_INTERVAL: usize = 2;
+    const REWIND_CAPACITY: usize = 600;
+    /// Held down to play the history backwards.
+    const REWIND_KEY: Keycode = Keycode::Backspace;
 }
 
 // --- Methods ---
 impl App {
-    pub fn new(file_name: &str, file_bytes: &[u8]) -> Result<Self> {
-        let mut vm = Vm::default();
-        vm.load_program(file_bytes);
+    pub fn new(args: &Args) -> Result<Self> {
+        let mut vm = Vm::with_platform(args.platform);
+        if let Some(quirks) = args.quirks {
+            vm.set_quirks(quirks);
+        }
+        if let Some(seed) = args.seed {
+            vm.set_random_source(SplitMix64::new(seed));
+        }
+        if let Some(addr) = args.load_addr {
+            vm.set_load_address(addr);
+        }
+        vm.load_program(&args.file_bytes)?;
+        if let Some(trace) = &args.trace {
+            vm.start_trace(BufWriter::new(File::create(trace)?));
+        }
 
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
 
-        let title = format!("Chimp-8 - {}", file_name);
+        let title = format!("Chimp-8 - {}", args.file_name);
 
         let window = video_subsystem
             .window(&title, Self::WINDOW_WIDTH, Self::WINDOW_HEIGHT)
             .position_centered()
             .build()?;
 
-        let canvas = window.into_canvas().present_vsync().build()?;
+        let canvas = window.into_canvas().build()?;
+        let mut renderer = Renderer::new(Self::SCALE as usize);
+        // the desktop always drew white on black
+        renderer.set_palette(args.palette.unwrap_or(Palette::MONOCHROME));
+        let framebuffer = renderer.buffer();
+
+        let is_sound_active = Arc::new(AtomicBool::new(false));
+        let speaker = match Self::open_speaker(&sdl_context, &is_sound_active) {
+            Ok(speaker) => Some(speaker),
+            Err(e) => {
+                eprintln!("Warning: no sound, {e}");
+                None
+            }
+        };
 
         Ok(Self {
             sdl_context,
             canvas,
+            renderer,
+            framebuffer,
+            _speaker: speaker,
+            is_sound_active,
             vm,
+            scheduler: Scheduler::new(args.ips.unwrap_or(Self::INSTRUCTIONS_PER_SECOND)),
+            rewind: Rewind::new(Self::REWIND_CAPACITY, Self::REWIND_INTERVAL),
+            title,
             is_running: true,
+            is_rewinding: false,
+            needs_redraw: true,
         })
     }
 
+    /// Runs until the window is closed, pacing the Vm by wall time so the
+    /// refresh rate of the monitor does not change the speed of the game.
+    ///
+    /// Presenting does not wait on vsync, the loop sleeps until the scheduler
+    /// has the next frame due instead.
+    #[allow(clippy::cast_possible_truncation)]
     pub fn run(&mut self) -> Result<()> {
         let mut event_pump = self.sdl_context.event_pump()?;
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::RGBA32,
+            self.renderer.width() as u32,
+            self.renderer.height() as u32,
+        )?;
+        let mut last_update = Instant::now();
         while self.is_running {
             self.process_events(&mut event_pump);
-            for _ in 0..Self::TICKS_PER_FRAME {
-                self.vm.tick();
+
+            let now = Instant::now();
+            self.scheduler.advance(now - last_update);
+            last_update = now;
+
+            let mut frames = 0;
+            while let Some(instructions) = self.scheduler.next_frame() {
+                frames += 1;
+                if !self.is_rewinding && !self.vm.is_halted() {
+                    self.run_frame(instructions)?;
+                    self.rewind.record(&self.vm);
+                }
+            }
+            if self.is_rewinding && frames > 0 {
+                self.rewind_frames(frames)?;
             }
-            self.vm.tick_timers();
-            self.draw_screen();
+
+            if self.vm.is_display_dirty() || self.needs_redraw {
+                self.draw_screen(&mut texture)?;
+                self.vm.acknowledge_display();
+                self.needs_redraw = false;
+            }
+            self.update_sound();
+            thread::sleep(self.scheduler.time_until_next_frame());
         }
 
+        self.vm.stop_trace()?;
+        Ok(())
+    }
+
+    fn run_frame(&mut self, instructions: u32) -> Result<()> {
+        match self.vm.run_frame(instructions).error {
+            Some(e) => self.show_error(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Opens the default audio device and starts playing the buzzer through it.
+    fn open_speaker(
+        sdl_context: &Sdl,
+        is_active: &Arc<AtomicBool>,
+    ) -> Result<AudioDevice<Speaker>> {
+        let desired = AudioSpecDesired {
+            freq: Some(Self::SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(512),
+        };
+        let speaker = sdl_context
+            .audio()?
+            .open_playback(None, &desired, |spec| Speaker {
+                beeper: Beeper::new(spec.freq.unsigned_abs()),
+                is_active: Arc::clone(is_active),
+            })?;
+        speaker.resume();
+        Ok(speaker)
+    }
+
+    /// Lets the buzzer sound while the sound timer runs, but not while rewinding.
+    fn update_sound(&self) {
+        let is_active = self.vm.is_sound_active() && !self.is_rewinding && !self.vm.is_halted();
+        self.is_sound_active.store(is_active, Ordering::Relaxed);
+    }
+
+    /// Steps back about `frames` frames, which also resumes a halted Vm.
+    fn rewind_frames(&mut self, frames: usize) -> Result<()> {
+        let was_halted = self.vm.is_halted();
+        self.rewind.rewind(&mut self.vm, frames);
+        if was_halted && !self.vm.is_halted() {
+            self.canvas.window_mut().set_title(&self.title)?;
+        }
+        Ok(())
+    }
+
+    /// Reports a halting error on stderr and in the window title.
+    fn show_error(&mut self, error: VmError) -> Result<()> {
+        eprintln!("Error: {error}");
+        let title = format!("{} - halted: {error}", self.title);
+        self.canvas.window_mut().set_title(&title)?;
         Ok(())
     }
 
@@ -74,18 +242,30 @@ impl App {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => self.is_running = false,
+                Event::KeyDown {
+                    keycode: Some(Self::REWIND_KEY),
+                    ..
+                } => self.is_rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Self::REWIND_KEY),
+                    ..
+                } => self.is_rewinding = false,
+                Event::Window {
+                    win_event: WindowEvent::Exposed | WindowEvent::SizeChanged(..),
+                    ..
+                } => self.needs_redraw = true,
                 Event::KeyDown {
                     keycode: Some(key), ..
                 } => {
                     if let Some(k) = Self::keycode_to_hex(key) {
-                        self.vm.keypress(k, true)
+                        self.vm.keypress(k, true);
                     }
                 }
                 Event::KeyUp {
                     keycode: Some(key), ..
                 } => {
                     if let Some(k) = Self::keycode_to_hex(key) {
-                        self.vm.keypress(k, false)
+                        self.vm.keypress(k, false);
                     }
                 }
                 _ => (),
@@ -93,28 +273,26 @@ impl App {
         }
     }
 
+    /// Renders what changed since the last call, uploads it and presents the frame.
     #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
-    fn draw_screen(&mut self) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-
-        let screen_buf = self.vm.get_display();
-        self.canvas.set_draw_color(Color::WHITE);
-        for (i, pixel) in screen_buf.iter().enumerate() {
-            if *pixel {
-                let x = (i % Vm::SCREEN_WIDTH) as u32;
-                let y = (i / Vm::SCREEN_WIDTH) as u32;
-
-                let rect = Rect::new(
-                    (x * Self::SCALE) as i32,
-                    (y * Self::SCALE) as i32,
-                    Self::SCALE,
-                    Self::SCALE,
-                );
-                self.canvas.fill_rect(rect).unwrap();
-            }
+    fn draw_screen(&mut self, texture: &mut Texture) -> Result<()> {
+        self.renderer.render_dirty(&self.vm, &mut self.framebuffer);
+        let pitch = self.renderer.pitch();
+        for rect in self.vm.dirty_rects() {
+            let area = self.renderer.buffer_rect(&self.vm, rect);
+            let offset = area.y * pitch + area.x * Renderer::BYTES_PER_PIXEL;
+            let rect = Rect::new(
+                area.x as i32,
+                area.y as i32,
+                area.width as u32,
+                area.height as u32,
+            );
+            texture.update(rect, &self.framebuffer[offset..], pitch)?;
         }
+
+        self.canvas.copy(texture, None, None)?;
         self.canvas.present();
+        Ok(())
     }
 
     fn keycode_to_hex(key: Keycode) -> Option<usize> {
@@ -144,33 +322,109 @@ impl App {
     }
 }
 
-fn parse_args() -> Result<(String, Vec<u8>)> {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() != 2 {
-        return Err(Error::from(
-            "Invalid arguments!\n\
-             Usage: chimp_desktop path/to/rom/file",
-        ));
+struct Args {
+    file_name: String,
+    file_bytes: Vec<u8>,
+    platform: Platform,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    trace: Option<String>,
+    load_addr: Option<u16>,
+    ips: Option<u32>,
+    palette: Option<Palette>,
+}
+
+fn usage_error() -> Error {
+    Error::from(format!(
+        "Invalid arguments!\n\
+         Usage: chimp_desktop [--platform {}] [--quirks {}] [--seed N] [--ips N] [--palette {}] [--load-address ADDR] [--trace path/to/log] path/to/rom/file",
+        Platform::NAMES.join("|"),
+        Quirks::PRESET_NAMES.join("|"),
+        Palette::PRESET_NAMES.join("|")
+    ))
+}
+
+fn parse_args() -> Result<Args> {
+    let mut file_name = None;
+    let mut platform = Platform::default();
+    let mut quirks = None;
+    let mut seed = None;
+    let mut trace = None;
+    let mut load_addr = None;
+    let mut ips = None;
+    let mut palette = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                let name = args.next().ok_or_else(usage_error)?;
+                platform = Platform::from_name(&name)
+                    .ok_or_else(|| Error::from(format!("Unknown platform: {name}")))?;
+            }
+            "--quirks" => {
+                let name = args.next().ok_or_else(usage_error)?;
+                let preset = Quirks::from_name(&name)
+                    .ok_or_else(|| Error::from(format!("Unknown quirks preset: {name}")))?;
+                quirks = Some(preset);
+            }
+            "--seed" => {
+                let value = args.next().ok_or_else(usage_error)?;
+                seed = Some(value.parse()?);
+            }
+            "--ips" => {
+                let value = args.next().ok_or_else(usage_error)?;
+                ips = Some(value.parse()?);
+            }
+            "--palette" => {
+                let name = args.next().ok_or_else(usage_error)?;
+                let preset = Palette::from_name(&name)
+                    .ok_or_else(|| Error::from(format!("Unknown palette: {name}")))?;
+                palette = Some(preset);
+            }
+            "--load-address" => {
+                let value = args.next().ok_or_else(usage_error)?;
+                let addr = match value.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16)?,
+                    None => value.parse()?,
+                };
+                load_addr = Some(addr);
+            }
+            "--trace" => trace = Some(args.next().ok_or_else(usage_error)?),
+            _ if file_name.is_none() => file_name = Some(arg),
+            _ => return Err(usage_error()),
+        }
     }
 
-    let file_name = args[1].clone();
+    let file_name = file_name.ok_or_else(usage_error)?;
     let file_bytes = match std::fs::read(&file_name) {
         Ok(bytes) => bytes,
         Err(e) => return Err(Box::new(e)),
     };
 
-    Ok((file_name, file_bytes))
+    Ok(Args {
+        file_name,
+        file_bytes,
+        platform,
+        quirks,
+        seed,
+        trace,
+        load_addr,
+        ips,
+        palette,
+    })
 }
 
 fn main() {
-    let (file_name, file_bytes) = match parse_args() {
+    let args = match parse_args() {
         Ok(data) => data,
         Err(e) => {
-            eprintln!("Error: {}", e);
+            eprintln!("Error: {e}");
             return;
         }
     };
 
-    let mut app = App::new(&file_name, &file_bytes).unwrap();
-    app.run().unwrap();
+    if let Err(e) = App::new(&args).and_then(|mut app| app.run()) {
+        eprintln!("Error: {e}");
+    }
 }
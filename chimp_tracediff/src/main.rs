@@ -0,0 +1,312 @@
+#![warn(clippy::pedantic, clippy::all)]
+use chimp_core::TraceLine;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines, Write};
+
+type Error = Box<dyn std::error::Error>;
+type Result<T> = std::result::Result<T, Error>;
+
+/// Lines shown before and after the first difference, unless `--context`
+/// says otherwise.
+const DEFAULT_CONTEXT: usize = 5;
+
+struct Args {
+    first: String,
+    second: String,
+    context: usize,
+}
+
+/// A trace file, read one line at a time since traces get very long.
+struct Trace<R> {
+    name: String,
+    lines: Lines<R>,
+    line_number: usize,
+}
+
+impl Trace<BufReader<File>> {
+    fn open(name: &str) -> Result<Self> {
+        let file = File::open(name).map_err(|e| Error::from(format!("{name}: {e}")))?;
+        Ok(Self::new(name, BufReader::new(file)))
+    }
+}
+
+impl<R: BufRead> Trace<R> {
+    fn new(name: &str, reader: R) -> Self {
+        Self {
+            name: name.to_string(),
+            lines: reader.lines(),
+            line_number: 0,
+        }
+    }
+
+    /// Returns the next line that is not blank, or `None` at the end of the file.
+    fn next_line(&mut self) -> Result<Option<TraceLine>> {
+        for line in &mut self.lines {
+            self.line_number += 1;
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return line
+                .parse()
+                .map(Some)
+                .map_err(|e| Error::from(format!("{}:{}: {e}", self.name, self.line_number)));
+        }
+        Ok(None)
+    }
+}
+
+fn usage_error() -> Error {
+    Error::from(
+        "Invalid arguments!\n\
+         Usage: chimp_tracediff [--context N] path/to/first.log path/to/second.log",
+    )
+}
+
+fn parse_args() -> Result<Args> {
+    let mut first = None;
+    let mut second = None;
+    let mut context = DEFAULT_CONTEXT;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = args.next().ok_or_else(usage_error)?.parse()?,
+            _ if first.is_none() => first = Some(arg),
+            _ if second.is_none() => second = Some(arg),
+            _ => return Err(usage_error()),
+        }
+    }
+
+    Ok(Args {
+        first: first.ok_or_else(usage_error)?,
+        second: second.ok_or_else(usage_error)?,
+        context,
+    })
+}
+
+/// Compares the traces cycle by cycle, writing the result to `out` and
+/// returning `true` if they match.
+///
+/// Lines are aligned by their cycle, so a trace started later than the other
+/// is compared from its first cycle on.
+fn compare(
+    mut first: Trace<impl BufRead>,
+    mut second: Trace<impl BufRead>,
+    context: usize,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let mut before = VecDeque::with_capacity(context);
+    let mut matched = 0;
+
+    let mut a = first.next_line()?;
+    let mut b = second.next_line()?;
+    loop {
+        match (&a, &b) {
+            (Some(x), Some(y)) if x.cycle < y.cycle => a = first.next_line()?,
+            (Some(x), Some(y)) if x.cycle > y.cycle => b = second.next_line()?,
+            (Some(x), Some(y)) => {
+                let diff = x.diff(y);
+                if !diff.is_empty() {
+                    report_difference(out, &first.name, &second.name, &before, x, y, &diff)?;
+                    report_after(out, &mut first, context, "<")?;
+                    report_after(out, &mut second, context, ">")?;
+                    return Ok(false);
+                }
+                if before.len() == context {
+                    before.pop_front();
+                }
+                if context > 0 {
+                    before.push_back(x.clone());
+                }
+                matched += 1;
+                a = first.next_line()?;
+                b = second.next_line()?;
+            }
+            (Some(x), None) => {
+                writeln!(
+                    out,
+                    "{} ends before cycle {}, {matched} instructions matched",
+                    second.name, x.cycle
+                )?;
+                return Ok(false);
+            }
+            (None, Some(y)) => {
+                writeln!(
+                    out,
+                    "{} ends before cycle {}, {matched} instructions matched",
+                    first.name, y.cycle
+                )?;
+                return Ok(false);
+            }
+            (None, None) => {
+                writeln!(out, "traces match, {matched} instructions compared")?;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn report_difference(
+    out: &mut impl Write,
+    first: &str,
+    second: &str,
+    before: &VecDeque<TraceLine>,
+    a: &TraceLine,
+    b: &TraceLine,
+    diff: &[(&str, &str, &str)],
+) -> io::Result<()> {
+    writeln!(out, "first difference at cycle {}", a.cycle)?;
+    if let Some(previous) = before.back() {
+        writeln!(out, "after executing: {}", previous.disassembly)?;
+    }
+    writeln!(out, "< {first}\n> {second}\n")?;
+
+    for line in before {
+        writeln!(out, "  {line}")?;
+    }
+    writeln!(out, "< {a}\n> {b}\n")?;
+
+    let width = diff
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, first, second) in diff {
+        writeln!(out, "  {name:<width$}  {first} -> {second}")?;
+    }
+    if diff.iter().any(|(name, _, _)| *name == "MEM") {
+        writeln!(
+            out,
+            "\nthe memory differs, it was written by the previous instruction"
+        )?;
+    }
+    Ok(())
+}
+
+/// Shows the `context` lines of `trace` following the difference, marked with `side`.
+fn report_after(
+    out: &mut impl Write,
+    trace: &mut Trace<impl BufRead>,
+    context: usize,
+    side: &str,
+) -> Result<()> {
+    if context == 0 {
+        return Ok(());
+    }
+    writeln!(out, "\nthen in {}:", trace.name)?;
+    for _ in 0..context {
+        let Some(line) = trace.next_line()? else {
+            writeln!(out, "{side} (end of trace)")?;
+            break;
+        };
+        writeln!(out, "{side} {line}")?;
+    }
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<bool> {
+    let first = Trace::open(&args.first)?;
+    let second = Trace::open(&args.second)?;
+    compare(first, second, args.context, &mut io::stdout().lock())
+}
+
+fn main() {
+    match parse_args().and_then(|args| run(&args)) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(name: &str, lines: &[&str]) -> Trace<io::Cursor<String>> {
+        Trace::new(name, io::Cursor::new(lines.join("\n")))
+    }
+
+    fn compare_lines(first: &[&str], second: &[&str], context: usize) -> (bool, String) {
+        let mut out = Vec::new();
+        let matched = compare(
+            trace("a.log", first),
+            trace("b.log", second),
+            context,
+            &mut out,
+        )
+        .unwrap();
+        (matched, String::from_utf8(out).unwrap())
+    }
+
+    const COMMON: [&str; 3] = [
+        "0 PC=0200 VA=00 MEM=11 ; LD VA, 0x02",
+        "1 PC=0202 VA=02 MEM=11 ; LD [I], VA",
+        "2 PC=0204 VA=02 MEM=22 ; ADD VA, 0x01",
+    ];
+
+    #[test]
+    fn matches_identical_traces() {
+        let (matched, out) = compare_lines(&COMMON, &COMMON, 2);
+        assert!(matched);
+        assert_eq!(out, "traces match, 3 instructions compared\n");
+    }
+
+    #[test]
+    fn aligns_traces_by_cycle() {
+        let (matched, out) = compare_lines(&COMMON, &COMMON[1..], 2);
+        assert!(matched);
+        assert_eq!(out, "traces match, 2 instructions compared\n");
+    }
+
+    #[test]
+    fn shows_the_context_around_a_difference() {
+        let mut first = COMMON.to_vec();
+        first.extend([
+            "3 PC=0206 VA=03 MEM=22 ; CLS",
+            "4 PC=0208 VA=03 MEM=22 ; RET",
+        ]);
+        let second = [
+            COMMON[0],
+            COMMON[1],
+            "2 PC=0204 VA=02 MEM=33 ; ADD VA, 0x01",
+            "3 PC=0206 VA=03 MEM=33 ; CLS",
+        ];
+        let (matched, out) = compare_lines(&first, &second, 1);
+        assert!(!matched);
+        assert_eq!(
+            out,
+            "first difference at cycle 2\n\
+             after executing: LD [I], VA\n\
+             < a.log\n\
+             > b.log\n\
+             \n  1 PC=0202 VA=02 MEM=11 ; LD [I], VA\n\
+             < 2 PC=0204 VA=02 MEM=22 ; ADD VA, 0x01\n\
+             > 2 PC=0204 VA=02 MEM=33 ; ADD VA, 0x01\n\
+             \n  MEM  22 -> 33\n\
+             \nthe memory differs, it was written by the previous instruction\n\
+             \nthen in a.log:\n\
+             < 3 PC=0206 VA=03 MEM=22 ; CLS\n\
+             \nthen in b.log:\n\
+             > 3 PC=0206 VA=03 MEM=33 ; CLS\n"
+        );
+    }
+
+    #[test]
+    fn reports_the_end_of_a_trace() {
+        let (matched, out) = compare_lines(&COMMON, &COMMON[..2], 2);
+        assert!(!matched);
+        assert_eq!(out, "b.log ends before cycle 2, 2 instructions matched\n");
+
+        let second = [COMMON[0], "1 PC=0202 VA=03 MEM=11 ; LD [I], VA"];
+        let (_, out) = compare_lines(&COMMON[..2], &second, 2);
+        assert!(out.ends_with(
+            "  VA  02 -> 03\n\nthen in a.log:\n< (end of trace)\n\nthen in b.log:\n> (end of trace)\n"
+        ));
+    }
+}
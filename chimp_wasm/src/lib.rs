@@ -1,15 +1,54 @@
 #![warn(clippy::pedantic, clippy::all)]
 
-use chimp_core::Vm;
+mod main_loop;
+mod speaker;
+
+use chimp_core::{Palette, Platform, Quirks, Renderer, Rewind, Scheduler, Vm};
 use js_sys::Uint8Array;
+use main_loop::MainLoop;
+use speaker::Speaker;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, Document, HtmlCanvasElement, KeyboardEvent};
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{
+    CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, ImageData, KeyboardEvent,
+};
 
 #[wasm_bindgen]
 pub struct VmWasm {
+    emulator: Rc<RefCell<Emulator>>,
+    /// `None` until `start` is called.
+    main_loop: Option<MainLoop>,
+}
+
+/// Everything a frame needs, shared between `VmWasm` and its main loop.
+struct Emulator {
     vm: Vm,
+    scheduler: Scheduler,
+    rewind: Rewind,
     ctx: CanvasRenderingContext2d,
+    renderer: Renderer,
+    /// The display as last rendered, copied to the canvas where it changed.
+    framebuffer: Vec<u8>,
+    /// Set when the whole canvas has to be painted again, e.g. in a new palette.
+    needs_redraw: bool,
+    status: Option<Element>,
+    /// `None` when the browser has no Web Audio, the game runs silently.
+    speaker: Option<Speaker>,
+    is_rewinding: bool,
+    is_paused: bool,
+    /// Time of the previous frame, in milliseconds, `None` after a pause.
+    last_update: Option<f64>,
+}
+
+// --- Constants ---
+impl Emulator {
+    const INSTRUCTIONS_PER_SECOND: u32 = 600;
+    /// Snapshot every other frame for 20 seconds of history.
+    const REWIND_INTERVAL: usize = 2;
+    const REWIND_CAPACITY: usize = 600;
 }
 
 #[allow(clippy::new_without_default)]
@@ -33,52 +72,236 @@ impl VmWasm {
             .dyn_into::<CanvasRenderingContext2d>()
             .unwrap();
 
-        Ok(VmWasm { vm, ctx })
+        // the canvas is as big as the high resolution display at an integer scale
+        let scale = canvas.width() as usize / Vm::MAX_SCREEN_WIDTH;
+        let renderer = Renderer::new(scale.max(1));
+        let framebuffer = renderer.buffer();
+
+        let status = document.get_element_by_id("vm-status");
+
+        let scheduler = Scheduler::new(Emulator::INSTRUCTIONS_PER_SECOND);
+        let rewind = Rewind::new(Emulator::REWIND_CAPACITY, Emulator::REWIND_INTERVAL);
+
+        let speaker = Speaker::new().ok();
+
+        let emulator = Emulator {
+            vm,
+            scheduler,
+            rewind,
+            ctx,
+            renderer,
+            framebuffer,
+            needs_redraw: true,
+            status,
+            speaker,
+            is_rewinding: false,
+            is_paused: false,
+            last_update: None,
+        };
+        Ok(VmWasm {
+            emulator: Rc::new(RefCell::new(emulator)),
+            main_loop: None,
+        })
     }
 
+    /// Runs the vm on every animation frame until `stop` is called, the
+    /// emulation speed being kept by the clock rather than the refresh rate.
+    #[allow(clippy::missing_errors_doc)]
     #[wasm_bindgen]
-    pub fn load_game(&mut self, data: &Uint8Array) {
-        self.vm.load_program(&data.to_vec());
+    pub fn start(&mut self) -> Result<(), JsValue> {
+        if self.main_loop.is_some() {
+            return Ok(());
+        }
+        let emulator = Rc::clone(&self.emulator);
+        self.main_loop = Some(MainLoop::start(move |now| {
+            emulator.borrow_mut().update(now);
+        })?);
+        Ok(())
     }
 
-    #[allow(clippy::cast_precision_loss)]
+    /// Stops the main loop and the buzzer, leaving the last frame on screen.
     #[wasm_bindgen]
-    pub fn draw(&mut self, scale: usize) {
-        let pixels = self.vm.get_display();
-        for (i, px) in pixels.iter().enumerate() {
-            if *px {
-                let x = i % Vm::SCREEN_WIDTH;
-                let y = i / Vm::SCREEN_WIDTH;
-                self.ctx.fill_rect(
-                    (x * scale) as f64,
-                    (y * scale) as f64,
-                    scale as f64,
-                    scale as f64,
-                );
-            }
+    pub fn stop(&mut self) {
+        self.main_loop = None;
+        let mut emulator = self.emulator.borrow_mut();
+        emulator.last_update = None;
+        if let Some(speaker) = &mut emulator.speaker {
+            speaker.set_sounding(false);
         }
     }
 
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn is_running(&self) -> bool {
+        self.main_loop.is_some()
+    }
+
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn is_paused(&self) -> bool {
+        self.emulator.borrow().is_paused
+    }
+
+    /// Freezes the vm while the main loop keeps drawing, e.g. when the page is hidden.
+    #[wasm_bindgen]
+    pub fn set_paused(&mut self, is_paused: bool) {
+        let mut emulator = self.emulator.borrow_mut();
+        emulator.is_paused = is_paused;
+        emulator.last_update = None;
+        emulator.scheduler.clear();
+        // a hidden page gets no more frames to silence the buzzer in
+        emulator.update_sound();
+    }
+
+    /// Plays the history backwards while set, one frame per frame.
+    #[wasm_bindgen]
+    pub fn set_rewinding(&mut self, is_rewinding: bool) {
+        self.emulator.borrow_mut().is_rewinding = is_rewinding;
+    }
+
+    /// Emulation speed, the timers always tick at 60 Hz.
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn instructions_per_second(&self) -> u32 {
+        self.emulator.borrow().scheduler.instructions_per_second()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.emulator
+            .borrow_mut()
+            .scheduler
+            .set_instructions_per_second(instructions_per_second);
+    }
+
+    /// Switches to a platform by name, e.g. "chip8" or "schip", and resets the vm.
+    #[allow(clippy::missing_errors_doc)]
+    #[wasm_bindgen]
+    pub fn set_platform(&mut self, name: &str) -> Result<(), JsValue> {
+        let platform = Platform::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown platform: {name}")))?;
+        self.emulator.borrow_mut().vm.set_platform(platform);
+        Ok(())
+    }
+
+    /// Selects one of the quirks presets by name, e.g. "vip" or "schip".
+    #[allow(clippy::missing_errors_doc)]
+    #[wasm_bindgen]
+    pub fn set_quirks(&mut self, name: &str) -> Result<(), JsValue> {
+        let quirks = Quirks::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown quirks preset: {name}")))?;
+        self.emulator.borrow_mut().vm.set_quirks(quirks);
+        Ok(())
+    }
+
+    /// Selects one of the palette presets by name, e.g. "amber" or "lcd", and
+    /// repaints the canvas with it, also before a game runs.
+    #[allow(clippy::missing_errors_doc)]
+    #[wasm_bindgen]
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsValue> {
+        let palette = Palette::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown palette: {name}")))?;
+        let mut emulator = self.emulator.borrow_mut();
+        emulator.renderer.set_palette(palette);
+        emulator.needs_redraw = true;
+        emulator.draw();
+        Ok(())
+    }
+
+    /// Loads a rom, failing if it does not fit in the memory of the platform.
+    #[allow(clippy::missing_errors_doc)]
+    #[wasm_bindgen]
+    pub fn load_game(&mut self, data: &Uint8Array) -> Result<(), JsValue> {
+        let mut emulator = self.emulator.borrow_mut();
+        emulator
+            .vm
+            .load_program(&data.to_vec())
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        emulator.rewind.clear();
+        emulator.scheduler.clear();
+        emulator.update_sound();
+        Ok(())
+    }
+
+    /// Width of the display in the current resolution.
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn width(&self) -> usize {
+        self.emulator.borrow().vm.screen_width()
+    }
+
+    /// Height of the display in the current resolution.
+    #[must_use]
     #[wasm_bindgen]
-    pub fn tick(&mut self) {
-        self.vm.tick();
+    pub fn height(&self) -> usize {
+        self.emulator.borrow().vm.screen_height()
     }
 
+    /// Returns the error that halted the vm, if any.
+    #[must_use]
     #[wasm_bindgen]
-    pub fn tick_timers(&mut self) {
-        self.vm.tick_timers();
+    pub fn error(&self) -> Option<String> {
+        self.emulator.borrow().vm.error().map(|e| e.to_string())
     }
 
     #[wasm_bindgen]
     pub fn reset(&mut self) {
-        self.vm.reset();
+        let mut emulator = self.emulator.borrow_mut();
+        emulator.vm.reset();
+        emulator.rewind.clear();
+        emulator.scheduler.clear();
+        emulator.update_sound();
+    }
+
+    /// Lets the sound play, call it from a user input handler since browsers
+    /// keep audio suspended until the page gets one.
+    #[wasm_bindgen]
+    pub fn resume_audio(&self) {
+        if let Some(speaker) = &self.emulator.borrow().speaker {
+            speaker.resume();
+        }
+    }
+
+    /// Loudness of the buzzer, from 0 to 1.
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn volume(&self) -> f32 {
+        self.emulator
+            .borrow()
+            .speaker
+            .as_ref()
+            .map_or(0.0, Speaker::volume)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_volume(&mut self, volume: f32) {
+        if let Some(speaker) = &mut self.emulator.borrow_mut().speaker {
+            speaker.set_volume(volume);
+        }
+    }
+
+    #[must_use]
+    #[wasm_bindgen]
+    pub fn is_muted(&self) -> bool {
+        self.emulator
+            .borrow()
+            .speaker
+            .as_ref()
+            .is_none_or(Speaker::is_muted)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_muted(&mut self, is_muted: bool) {
+        if let Some(speaker) = &mut self.emulator.borrow_mut().speaker {
+            speaker.set_muted(is_muted);
+        }
     }
 
     #[wasm_bindgen]
     pub fn keypress(&mut self, event: &KeyboardEvent, pressed: bool) {
         let keycode = event.key();
         if let Some(key) = Self::key_to_hex(&keycode) {
-            self.vm.keypress(key, pressed)
+            self.emulator.borrow_mut().vm.keypress(key, pressed);
         }
     }
 
@@ -108,3 +331,100 @@ impl VmWasm {
         }
     }
 }
+
+// --- Private Methods ---
+impl Emulator {
+    /// Runs the frames due since the previous update, `now` being in milliseconds,
+    /// then presents the result.
+    fn update(&mut self, now: f64) {
+        if !self.is_paused {
+            let elapsed = self.last_update.map_or(0.0, |last| (now - last).max(0.0));
+            self.scheduler
+                .advance(Duration::from_secs_f64(elapsed / 1000.0));
+            self.last_update = Some(now);
+        }
+
+        let mut frames = 0;
+        while let Some(instructions) = self.scheduler.next_frame() {
+            frames += 1;
+            if !self.is_rewinding && !self.vm.is_halted() {
+                self.vm.run_frame(instructions);
+                self.rewind.record(&self.vm);
+            }
+        }
+        if self.is_rewinding && frames > 0 {
+            self.rewind.rewind(&mut self.vm, frames);
+        }
+
+        // the canvas keeps its picture, only what changed is repainted
+        if self.vm.is_display_dirty() || self.needs_redraw {
+            self.draw();
+            self.vm.acknowledge_display();
+        }
+        self.update_status();
+        self.update_sound();
+    }
+
+    /// Renders the changed parts of the display and copies them to the canvas.
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    fn draw(&mut self) {
+        if self.needs_redraw {
+            self.renderer.render(&self.vm, &mut self.framebuffer);
+        } else {
+            self.renderer.render_dirty(&self.vm, &mut self.framebuffer);
+        }
+
+        let image = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&self.framebuffer),
+            self.renderer.width() as u32,
+            self.renderer.height() as u32,
+        );
+        let Ok(image) = image else {
+            return;
+        };
+        if self.needs_redraw {
+            let _ = self.ctx.put_image_data(&image, 0.0, 0.0);
+            self.needs_redraw = false;
+            return;
+        }
+        for rect in self.vm.dirty_rects() {
+            let area = self.renderer.buffer_rect(&self.vm, rect);
+            let _ = self
+                .ctx
+                .put_image_data_with_dirty_x_and_dirty_y_and_dirty_width_and_dirty_height(
+                    &image,
+                    0.0,
+                    0.0,
+                    area.x as f64,
+                    area.y as f64,
+                    area.width as f64,
+                    area.height as f64,
+                );
+        }
+    }
+
+    /// Shows why the vm halted, a halted vm keeps its last frame on screen
+    /// until it is rewound or replaced.
+    fn update_status(&self) {
+        if let Some(status) = &self.status {
+            let text = self
+                .vm
+                .error()
+                .map(|e| format!("halted: {e}"))
+                .unwrap_or_default();
+            status.set_text_content(Some(&text));
+        }
+    }
+
+    /// Lets the buzzer sound while the sound timer runs on a running vm,
+    /// but not while paused or rewinding.
+    fn update_sound(&mut self) {
+        let is_active = self.vm.is_sound_active()
+            && !self.vm.is_halted()
+            && !self.is_paused
+            && !self.is_rewinding;
+        if let Some(speaker) = &mut self.speaker {
+            speaker.set_sounding(is_active);
+        }
+    }
+}
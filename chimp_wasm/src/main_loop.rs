@@ -0,0 +1,63 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{Performance, Window};
+
+type Callback = Rc<RefCell<Option<Closure<dyn FnMut()>>>>;
+
+/// Calls a function on every animation frame with the current time, in
+/// milliseconds, until it is dropped.
+///
+/// The time comes from `performance.now()`, so the caller can pace itself by
+/// it whatever the refresh rate of the monitor.
+pub(crate) struct MainLoop {
+    window: Window,
+    /// Handle of the requested animation frame, to cancel it.
+    handle: Rc<Cell<i32>>,
+    /// The closure requests itself for the next frame, dropping it breaks the cycle.
+    callback: Callback,
+}
+
+// --- Methods ---
+impl MainLoop {
+    pub(crate) fn start(mut on_frame: impl FnMut(f64) + 'static) -> Result<Self, JsValue> {
+        let window = web_sys::window().ok_or("no window")?;
+        let performance: Performance = window.performance().ok_or("no performance")?;
+
+        let handle = Rc::new(Cell::new(0));
+        let callback: Callback = Rc::new(RefCell::new(None));
+
+        let next_window = window.clone();
+        let next_handle = Rc::clone(&handle);
+        let next_callback = Rc::clone(&callback);
+        *callback.borrow_mut() = Some(Closure::new(move || {
+            on_frame(performance.now());
+            if let Some(callback) = next_callback.borrow().as_ref() {
+                if let Ok(id) = Self::request(&next_window, callback) {
+                    next_handle.set(id);
+                }
+            }
+        }));
+
+        let id = Self::request(&window, callback.borrow().as_ref().unwrap_throw())?;
+        handle.set(id);
+
+        Ok(Self {
+            window,
+            handle,
+            callback,
+        })
+    }
+
+    fn request(window: &Window, callback: &Closure<dyn FnMut()>) -> Result<i32, JsValue> {
+        window.request_animation_frame(callback.as_ref().unchecked_ref())
+    }
+}
+
+impl Drop for MainLoop {
+    fn drop(&mut self) {
+        let _ = self.window.cancel_animation_frame(self.handle.get());
+        self.callback.borrow_mut().take();
+    }
+}
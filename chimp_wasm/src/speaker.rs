@@ -0,0 +1,95 @@
+use chimp_core::Beeper;
+use wasm_bindgen::JsValue;
+use web_sys::{AudioContext, GainNode, OscillatorNode, OscillatorType};
+
+/// The buzzer, a square wave oscillator whose gain is turned up while it sounds.
+///
+/// Browsers start an `AudioContext` suspended until the page gets a user
+/// gesture, `resume` has to be called from an input event handler.
+pub(crate) struct Speaker {
+    ctx: AudioContext,
+    gain: GainNode,
+    _oscillator: OscillatorNode,
+    volume: f32,
+    is_muted: bool,
+    is_sounding: bool,
+}
+
+// --- Constants ---
+impl Speaker {
+    /// How fast the gain follows the buzzer, in seconds, short enough to sound
+    /// immediate and long enough not to click.
+    const FADE_TIME_CONSTANT: f64 = 0.005;
+}
+
+// --- Methods ---
+impl Speaker {
+    pub(crate) fn new() -> Result<Self, JsValue> {
+        let ctx = AudioContext::new()?;
+
+        let oscillator = ctx.create_oscillator()?;
+        oscillator.set_type(OscillatorType::Square);
+        oscillator.frequency().set_value(Beeper::DEFAULT_FREQUENCY);
+
+        let gain = ctx.create_gain()?;
+        gain.gain().set_value(0.0);
+
+        oscillator.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&ctx.destination())?;
+        oscillator.start()?;
+
+        Ok(Self {
+            ctx,
+            gain,
+            _oscillator: oscillator,
+            volume: Beeper::DEFAULT_VOLUME,
+            is_muted: false,
+            is_sounding: false,
+        })
+    }
+
+    /// Lets the audio play, only allowed while handling a user gesture.
+    pub(crate) fn resume(&self) {
+        let _ = self.ctx.resume();
+    }
+
+    pub(crate) fn set_sounding(&mut self, is_sounding: bool) {
+        if self.is_sounding != is_sounding {
+            self.is_sounding = is_sounding;
+            self.update_gain();
+        }
+    }
+
+    pub(crate) fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Changes the loudness, from 0 to 1. Anything that is not a number silences it.
+    pub(crate) fn set_volume(&mut self, volume: f32) {
+        let volume = if volume.is_finite() { volume } else { 0.0 };
+        self.volume = volume.clamp(0.0, 1.0);
+        self.update_gain();
+    }
+
+    pub(crate) fn is_muted(&self) -> bool {
+        self.is_muted
+    }
+
+    pub(crate) fn set_muted(&mut self, is_muted: bool) {
+        self.is_muted = is_muted;
+        self.update_gain();
+    }
+
+    fn update_gain(&self) {
+        let target = if self.is_sounding && !self.is_muted {
+            self.volume
+        } else {
+            0.0
+        };
+        let _ = self.gain.gain().set_target_at_time(
+            target,
+            self.ctx.current_time(),
+            Self::FADE_TIME_CONSTANT,
+        );
+    }
+}
@@ -2,21 +2,24 @@ import init, * as wasm from "./chimp_wasm.js"
 
 const WIDTH = 64
 const HEIGHT = 32
-const SCALE = 15
-const TICKS_PER_FRAME = 10
-let anim_frame = 0
+const SCALE = 16
+// held down to play the history backwards
+const REWIND_KEY = "Backspace"
+const PLATFORM_QUIRKS = { "chip8": "chimp", "schip": "schip", "xochip": "xochip" }
 
 let canvas = document.getElementById("canvas")
 canvas.width = WIDTH * SCALE
 canvas.height = HEIGHT * SCALE
 
-let ctx = canvas.getContext("2d")
-ctx.fillStyle = "#1D1A05"
-ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE)
-
 let file_input = document.getElementById("file-input")
 let file_input_div = document.getElementById("file-input-div")
 let rom_selector = document.getElementById("rom-selector")
+let quirks_selector = document.getElementById("quirks-selector")
+let platform_selector = document.getElementById("platform-selector")
+let palette_selector = document.getElementById("palette-selector")
+let mute_toggle = document.getElementById("mute-toggle")
+let volume_slider = document.getElementById("volume-slider")
+let ips_input = document.getElementById("ips-input")
 
 async function populate_rom_selector() {
     let file_url = new URL("roms/rom_list.txt",
@@ -44,20 +47,70 @@ async function run() {
     await init()
     let vm = new wasm.VmWasm()
 
+    // browsers keep the audio suspended until the page gets a user gesture
+    document.addEventListener("click", function () {
+        vm.resume_audio()
+    })
+
     document.addEventListener("keydown", function (event) {
+        vm.resume_audio()
+        if (event.key == REWIND_KEY) {
+            event.preventDefault()
+            vm.set_rewinding(true)
+            return
+        }
         vm.keypress(event, true)
     })
 
     document.addEventListener("keyup", function (event) {
+        if (event.key == REWIND_KEY) {
+            vm.set_rewinding(false)
+            return
+        }
         vm.keypress(event, false)
     })
 
-    file_input_div.addEventListener("change", function (event) {
-        rom_selector.selectedIndex = 0;
+    vm.set_muted(mute_toggle.checked)
+    mute_toggle.addEventListener("change", function (event) {
+        vm.set_muted(event.target.checked)
+    }, false)
 
-        if (anim_frame != 0) {
-            window.cancelAnimationFrame(anim_frame)
+    volume_slider.value = vm.volume()
+    volume_slider.addEventListener("input", function (event) {
+        vm.set_volume(parseFloat(event.target.value))
+    }, false)
+
+    ips_input.value = vm.instructions_per_second()
+    ips_input.addEventListener("change", function (event) {
+        let ips = parseInt(event.target.value)
+        if (ips > 0) {
+            vm.set_instructions_per_second(ips)
         }
+        event.target.value = vm.instructions_per_second()
+    }, false)
+
+    // the browser stops animation frames in hidden tabs, do not catch up on return
+    document.addEventListener("visibilitychange", function () {
+        vm.set_paused(document.hidden)
+    })
+
+    vm.set_palette(palette_selector.value)
+    palette_selector.addEventListener("change", function (event) {
+        vm.set_palette(event.target.value)
+    }, false)
+
+    vm.set_quirks(quirks_selector.value)
+    quirks_selector.addEventListener("change", function (event) {
+        vm.set_quirks(event.target.value)
+    }, false)
+
+    // the platform is applied when the next rom is loaded, suggest its quirks meanwhile
+    platform_selector.addEventListener("change", function (event) {
+        quirks_selector.value = PLATFORM_QUIRKS[event.target.value]
+    }, false)
+
+    file_input_div.addEventListener("change", function (event) {
+        rom_selector.selectedIndex = 0;
 
         let file = event.target.files[0]
         if (!file) {
@@ -69,9 +122,9 @@ async function run() {
         reader.onload = function () {
             let buffer = reader.result
             const rom = new Uint8Array(buffer)
-            vm.reset()
-            vm.load_game(rom)
-            main_loop(vm)
+            if (load_rom(vm, rom)) {
+                vm.start()
+            }
 
             console.log(vm)
         }
@@ -87,25 +140,33 @@ async function run() {
             return
         }
 
-        if (anim_frame != 0) {
-            window.cancelAnimationFrame(anim_frame)
-        }
-
         let rom_url = new URL(selector_value,
             import.meta.url)
 
         fetch(rom_url).then(file => file.arrayBuffer()).then(buffer => {
             const rom = new Uint8Array(buffer)
             console.log(buffer)
-            vm.reset()
-            vm.load_game(rom)
-            main_loop(vm)
+            if (load_rom(vm, rom)) {
+                vm.start()
+            }
 
             console.log(vm)
         })
     }, false)
 }
 
+function load_rom(vm, rom) {
+    vm.set_platform(platform_selector.value)
+    vm.set_quirks(quirks_selector.value)
+    try {
+        vm.load_game(rom)
+    } catch (error) {
+        alert(error)
+        return false
+    }
+    return true
+}
+
 function reset_input() {
     file_input.remove()
     let new_input = document.createElement('input')
@@ -115,20 +176,4 @@ function reset_input() {
     file_input = new_input
 }
 
-function main_loop(vm) {
-    for (let i = 0; i < TICKS_PER_FRAME; i++) {
-        vm.tick()
-    }
-    vm.tick_timers()
-
-    ctx.fillStyle = "#1D1A05"
-    ctx.fillRect(0, 0, WIDTH * SCALE, HEIGHT * SCALE)
-    ctx.fillStyle = "#fffbbd"
-    vm.draw(SCALE)
-
-    anim_frame = window.requestAnimationFrame(() => {
-        main_loop(vm)
-    })
-}
-
 run().catch(console.error)
\ No newline at end of file